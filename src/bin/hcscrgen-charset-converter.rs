@@ -1,4 +1,4 @@
-use clap::{arg, Parser};
+use clap::Parser;
 use image::{GenericImage, ImageReader, Rgb, RgbImage};
 
 #[derive(Parser, Debug)]
@@ -77,7 +77,7 @@ fn import_charset(args: &Args) -> Vec<RgbImage> {
     characters
 }
 
-fn store_charset(args: &Args, charset: &Vec<RgbImage>) {
+fn store_charset(args: &Args, charset: &[RgbImage]) {
     let mut output = RgbImage::from_pixel(
         1 + (args.width + 1) * 16,
         1 + (args.height + 1) * 16,
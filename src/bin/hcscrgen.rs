@@ -1,8 +1,20 @@
-use std::{fs::File, io::Write};
+use std::{
+    fs::File,
+    io::{BufReader, Write},
+    time::Duration,
+};
 
 use clap::Parser;
-use hcscrgen::convert;
-use image::{EncodableLayout, ImageReader};
+use hcscrgen::{
+    convert, ColorDistance, ConversionOptions, ConversionResult, Crop, Dithering, FitMode,
+    Preprocessing, Scaling, TextOverlay, TileMetricKind, Transparency, UnsharpMask, Window,
+};
+use image::{
+    codecs::gif::{GifDecoder, GifEncoder, Repeat},
+    imageops::FilterType,
+    AnimationDecoder, Delay, DynamicImage, EncodableLayout, Frame, ImageFormat, ImageReader, Rgb,
+    RgbImage,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -10,53 +22,610 @@ struct Args {
     /// Generate charset
     #[arg(short, long)]
     generate_charset: bool,
+    /// Image whose bright areas mark cells that should blink
+    #[arg(long)]
+    blink_mask: Option<String>,
+    /// Color distance metric (rgb, weighted-rgb, cie76, ciede2000)
+    #[arg(long, default_value = "rgb")]
+    color_distance: ColorDistance,
+    /// Metric for comparing tiles with characters (mae, squared, blurred, ssim)
+    #[arg(long)]
+    tile_metric: Option<TileMetricKind>,
+    /// Standard deviation of the blur of the blurred tile metric
+    #[arg(
+        long,
+        value_name = "SIGMA",
+        default_value = "1.0",
+        requires = "tile_metric"
+    )]
+    tile_metric_sigma: f32,
+    /// Match characters by comparing blurred cells (standard deviation of the blur, e.g. 1.0)
+    #[arg(long, value_name = "SIGMA")]
+    low_pass: Option<f32>,
+    /// Distribute the color error of each cell to the neighboring cells
+    #[arg(long)]
+    error_diffusion: bool,
+    /// Dither the image to the machine's palette first (floyd-steinberg, atkinson, bayer2, bayer4, bayer8)
+    #[arg(long)]
+    dither: Option<Dithering>,
+    /// Save the dithered image
+    #[arg(long, requires = "dither")]
+    save_dithered: bool,
+    /// Character codes that must not be used (e.g. 0x00-0x1f,0xff)
+    #[arg(long, value_parser = parse_codes)]
+    exclude: Option<Codes>,
+    /// Text to write into the screen as ROW,COLUMN,TEXT (can be used multiple times)
+    #[arg(long, value_parser = parse_text)]
+    text: Vec<Text>,
+    /// File containing texts to write into the screen (one ROW,COLUMN,TEXT per line)
+    #[arg(long)]
+    text_file: Option<String>,
+    /// Palette index of the text color
+    #[arg(long)]
+    text_color: Option<u8>,
+    /// Palette index of the text background color
+    #[arg(long)]
+    text_background: Option<u8>,
+    /// Save an image that shows the error of each cell
+    #[arg(long)]
+    heatmap: bool,
+    /// Optimize the characters of the whole screen for the passed number of seconds
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    optimize: Option<Duration>,
+    /// Search the best alignment of the character grid within the image
+    #[arg(long)]
+    align_grid: bool,
+    /// Stretch the brightness range of the image
+    #[arg(long)]
+    auto_levels: bool,
+    /// Gamma correction (values above 1 brighten the mid-tones)
+    #[arg(long)]
+    gamma: Option<f32>,
+    /// Contrast factor
+    #[arg(long)]
+    contrast: Option<f32>,
+    /// Saturation factor
+    #[arg(long)]
+    saturation: Option<f32>,
+    /// Sharpen the image (standard deviation of the blur that gets subtracted)
+    #[arg(long, value_name = "SIGMA")]
+    unsharp_mask: Option<f32>,
+    /// Minimal brightness difference for pixels to get sharpened
+    #[arg(long, default_value_t = 0, requires = "unsharp_mask")]
+    unsharp_threshold: i32,
+    /// Scale the image to the screen (none, stretch, fit, fill)
+    #[arg(long, default_value = "none")]
+    fit: FitMode,
+    /// Resampling filter (nearest, triangle, catmull-rom, gaussian, lanczos3)
+    #[arg(long, default_value = "lanczos3", value_parser = parse_filter)]
+    filter: FilterType,
+    /// Color of the area not covered by the image when using --fit fit (hex RRGGBB)
+    #[arg(long, default_value = "000000", value_parser = parse_color)]
+    fill_color: Rgb<u8>,
+    /// Width of a pixel relative to its height (overrides the profile's value)
+    #[arg(long)]
+    pixel_aspect_ratio: Option<f32>,
+    /// Render the preview with the pixel aspect ratio of the machine
+    #[arg(long)]
+    aspect_preview: bool,
+    /// Region of the image to use as X,Y,WIDTH,HEIGHT
+    #[arg(long, value_parser = parse_crop)]
+    crop: Option<Crop>,
+    /// Position of the screen's top left corner in the image as X,Y
+    #[arg(long, value_parser = parse_offset)]
+    offset: Option<(u32, u32)>,
+    /// Composite transparent pixels onto this color (RRGGBB)
+    #[arg(long, value_parser = parse_color)]
+    background_color: Option<Rgb<u8>>,
+    /// Fill fully transparent cells with this character code instead of matching them
+    #[arg(long, value_parser = parse_code)]
+    transparent_code: Option<u8>,
+    /// Convert the image into this area of the screen (ROW,COLUMN,LINES,COLUMNS)
+    #[arg(long, value_parser = parse_window)]
+    window: Option<Window>,
+    /// Only write the window's cells to character and color RAM (instead of a whole screen)
+    #[arg(long, requires = "window")]
+    compact_window: bool,
     /// Machine profile to use
     #[arg()]
     profile: String,
-    /// Image file to convert
-    #[arg()]
-    input_file: String,
+    /// Image file to convert (an animated GIF or several images are converted frame by frame)
+    #[arg(required = true)]
+    input_files: Vec<String>,
+    /// Delay between the frames of an image sequence in milliseconds
+    #[arg(long, default_value = "100")]
+    frame_delay: u32,
+    /// Write all frames of an animation to one file per kind (with a frame index) instead of one
+    /// file per frame
+    #[arg(long)]
+    concatenate: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Codes(Vec<u8>);
+
+/// Parse a comma separated list of codes and code ranges (decimal or hexadecimal with 0x prefix)
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds: f32 = s
+        .parse()
+        .map_err(|_| format!("Invalid number of seconds \"{}\".", s))?;
+
+    Duration::try_from_secs_f32(seconds)
+        .map_err(|_| format!("Invalid number of seconds \"{}\".", s))
+}
+
+fn parse_code(code: &str) -> Result<u8, String> {
+    let code = code.trim();
+    let parsed = match code.strip_prefix("0x").or(code.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => code.parse(),
+    };
+    parsed.map_err(|_| format!("Invalid character code \"{}\".", code))
+}
+
+fn parse_codes(s: &str) -> Result<Codes, String> {
+    let mut codes = Vec::new();
+    for part in s.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse_code(first)?, parse_code(last)?);
+                if first > last {
+                    return Err(format!(
+                        "Invalid range \"{}\", the first code must not be greater than the last one.",
+                        part.trim()
+                    ));
+                }
+                codes.extend(first..=last);
+            }
+            None => codes.push(parse_code(part)?),
+        }
+    }
+
+    Ok(Codes(codes))
+}
+
+/// Parse a comma separated list of the passed number of numbers
+fn parse_numbers(s: &str, count: usize, format: &str) -> Result<Vec<u32>, String> {
+    let numbers: Vec<u32> = s
+        .split(',')
+        .map(|number| number.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("Invalid value \"{}\", expected {}.", s, format))?;
+    if numbers.len() != count {
+        return Err(format!("Invalid value \"{}\", expected {}.", s, format));
+    }
+
+    Ok(numbers)
+}
+
+fn parse_crop(s: &str) -> Result<Crop, String> {
+    let numbers = parse_numbers(s, 4, "X,Y,WIDTH,HEIGHT")?;
+
+    Ok(Crop {
+        x: numbers[0],
+        y: numbers[1],
+        width: numbers[2],
+        height: numbers[3],
+    })
+}
+
+fn parse_window(s: &str) -> Result<Window, String> {
+    let numbers = parse_numbers(s, 4, "ROW,COLUMN,LINES,COLUMNS")?;
+
+    Ok(Window {
+        row: numbers[0],
+        column: numbers[1],
+        lines: numbers[2],
+        columns: numbers[3],
+    })
+}
+
+fn parse_offset(s: &str) -> Result<(u32, u32), String> {
+    let numbers = parse_numbers(s, 2, "X,Y")?;
+
+    Ok((numbers[0], numbers[1]))
+}
+
+fn parse_filter(s: &str) -> Result<FilterType, String> {
+    match s {
+        "nearest" => Ok(FilterType::Nearest),
+        "triangle" => Ok(FilterType::Triangle),
+        "catmull-rom" => Ok(FilterType::CatmullRom),
+        "gaussian" => Ok(FilterType::Gaussian),
+        "lanczos3" => Ok(FilterType::Lanczos3),
+        _ => Err(format!("Unknown filter \"{}\".", s)),
+    }
+}
+
+/// Parse a color in hexadecimal notation (RRGGBB, optionally prefixed by #)
+fn parse_color(s: &str) -> Result<Rgb<u8>, String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let value = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or(format!("Invalid color \"{}\", expected RRGGBB.", s))?;
+
+    Ok(Rgb([(value >> 16) as u8, (value >> 8) as u8, value as u8]))
+}
+
+#[derive(Clone, Debug)]
+struct Text {
+    row: u32,
+    column: u32,
+    text: String,
+}
+
+/// Parse a text overlay in the form ROW,COLUMN,TEXT (the text may contain commas)
+fn parse_text(s: &str) -> Result<Text, String> {
+    let error = || format!("Invalid text \"{}\", expected ROW,COLUMN,TEXT.", s);
+    let mut parts = s.splitn(3, ',');
+    let mut next_number = || {
+        parts
+            .next()
+            .and_then(|part| part.trim().parse().ok())
+            .ok_or_else(error)
+    };
+    let row = next_number()?;
+    let column = next_number()?;
+    let text = parts.next().ok_or_else(error)?.to_string();
+
+    Ok(Text { row, column, text })
+}
+
+fn print_statistics(result: &ConversionResult) {
+    let mean_error = result
+        .cell_errors
+        .iter()
+        .map(|&error| error as f64)
+        .sum::<f64>()
+        / result.cell_errors.len() as f64;
+
+    println!("Preprocessing: {}", result.preprocessing);
+    println!("Mean error: {:.2}", mean_error);
+    match result.psnr {
+        Some(psnr) => println!("PSNR: {:.2} dB", psnr),
+        None => println!("PSNR: infinite (the preview is identical to the image)"),
+    }
+    println!("Distinct codes: {}", result.distinct_codes);
+    if let Some(optimization) = &result.optimization {
+        if optimization.initial_error > 0.0 {
+            println!(
+                "Optimization: blurred error {:.2} -> {:.2} ({:.1}% better)",
+                optimization.initial_error,
+                optimization.final_error,
+                100.0 * (1.0 - optimization.final_error / optimization.initial_error)
+            );
+        } else {
+            println!("Optimization: blurred error 0.00, nothing to improve");
+        }
+    }
+
+    let mut cells: Vec<usize> = (0..result.cell_errors.len()).collect();
+    cells.sort_by_key(|&cell| std::cmp::Reverse(result.cell_errors[cell]));
+    for &cell in cells.iter().take(5) {
+        println!(
+            "Worst cell: row {}, column {} (error {})",
+            cell as u32 / result.columns,
+            cell as u32 % result.columns,
+            result.cell_errors[cell]
+        );
+    }
 }
 
 fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
+
+    // outputs are named after the first input file
+    let input_file = &args.input_files[0];
 
-    let input_file = &args.input_file;
+    let frames = load_frames(&args.input_files, args.frame_delay);
 
-    let input_img = ImageReader::open(input_file)
-        .expect("Unable to read image")
-        .decode()
-        .expect("Unable to decode image");
+    let blink_mask = args.blink_mask.as_ref().map(|blink_mask_file| {
+        ImageReader::open(blink_mask_file)
+            .expect("Unable to read blink mask")
+            .decode()
+            .expect("Unable to decode blink mask")
+    });
 
-    match convert(&input_img, &args.profile, args.generate_charset) {
+    let color_distance = args.color_distance;
+    let tile_metric = match args
+        .tile_metric
+        .map(|tile_metric| tile_metric.create(color_distance, args.tile_metric_sigma))
+        .transpose()
+    {
+        Ok(tile_metric) => tile_metric,
         Err(error_message) => {
             eprintln!("Error while converting: {}", error_message);
             return;
         }
-        Ok(result) => {
-            result
-                .preview
-                .save(format!("{}.preview.png", input_file))
-                .expect("Unable to store preview image.");
-
-            File::create(format!("{}.chars.bin", input_file))
-                .expect("Unable to open character ram output file.")
-                .write_all(&result.character_ram.as_bytes())
-                .expect("Unable to write to character ram output file.");
-
-            if let Some(color_ram) = result.color_ram {
-                File::create(format!("{}.color.bin", input_file))
-                    .expect("Unable to open color ram output file.")
-                    .write_all(&color_ram.as_bytes())
-                    .expect("Unable to write to color ram output file.");
+    };
+
+    let mut texts = std::mem::take(&mut args.text);
+    if let Some(text_file) = &args.text_file {
+        let content = std::fs::read_to_string(text_file).expect("Unable to read text file");
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            match parse_text(line) {
+                Ok(text) => texts.push(text),
+                Err(error_message) => {
+                    eprintln!("Error while reading text file: {}", error_message);
+                    return;
+                }
             }
+        }
+    }
+    let text_overlays: Vec<TextOverlay> = texts
+        .into_iter()
+        .map(|text| TextOverlay {
+            row: text.row,
+            column: text.column,
+            text: text.text,
+            foreground: args.text_color,
+            background: args.text_background,
+        })
+        .collect();
 
-            if let Some(charset) = result.charset {
-                File::create(format!("{}.charset.bin", input_file))
-                    .expect("Unable to open charset output file.")
-                    .write_all(&charset.as_bytes())
-                    .expect("Unable to write to charset output file.");
+    let options = ConversionOptions {
+        generate_charset: args.generate_charset,
+        blink_mask: blink_mask.as_ref(),
+        color_distance,
+        tile_metric: tile_metric.as_deref(),
+        low_pass_sigma: args.low_pass,
+        error_diffusion: args.error_diffusion,
+        dithering: args.dither,
+        text_overlays: &text_overlays,
+        optimization_time: args.optimize,
+        align_grid: args.align_grid,
+        scaling: Scaling {
+            fit_mode: args.fit,
+            filter: args.filter,
+            fill_color: args.fill_color,
+        },
+        pixel_aspect_ratio: args.pixel_aspect_ratio,
+        crop: args.crop,
+        offset: args.offset.unwrap_or_default(),
+        window: args.window,
+        compact_window: args.compact_window,
+        transparency: (args.background_color.is_some() || args.transparent_code.is_some()).then(
+            || Transparency {
+                background: args.background_color.unwrap_or(Rgb([0x00, 0x00, 0x00])),
+                fill_code: args.transparent_code,
+            },
+        ),
+        preprocessing: Preprocessing {
+            auto_levels: args.auto_levels,
+            gamma: args.gamma,
+            contrast: args.contrast,
+            saturation: args.saturation,
+            unsharp_mask: args.unsharp_mask.map(|sigma| UnsharpMask {
+                sigma,
+                threshold: args.unsharp_threshold,
+            }),
+        },
+        excluded_codes: args.exclude.as_ref().map_or(&[], |codes| &codes.0),
+    };
+
+    let mut results = Vec::new();
+    for (number, (frame, _)) in frames.iter().enumerate() {
+        match convert(frame, &args.profile, &options) {
+            Err(error_message) if frames.len() > 1 => {
+                eprintln!("Error while converting frame {}: {}", number, error_message);
+                return;
+            }
+            Err(error_message) => {
+                eprintln!("Error while converting: {}", error_message);
+                return;
             }
+            Ok(result) => results.push(result),
+        }
+    }
+
+    if let [result] = &results[..] {
+        print_information(&args, result);
+        write_images(&args, result, input_file);
+        get_preview(&args, result)
+            .save(format!("{}.preview.png", input_file))
+            .expect("Unable to store preview image.");
+        write_binaries(result, input_file);
+        return;
+    }
+
+    for (number, result) in results.iter().enumerate() {
+        println!("Frame {}:", number);
+        print_information(&args, result);
+        let frame_prefix = format!("{}.frame{:04}", input_file, number);
+        write_images(&args, result, &frame_prefix);
+        if !args.concatenate {
+            write_binaries(result, &frame_prefix);
+        }
+    }
+    if args.concatenate {
+        write_concatenated_binaries(&results, &frames, input_file);
+    }
+
+    let preview_frames = results.iter().zip(&frames).map(|(result, (_, delay))| {
+        Frame::from_parts(
+            DynamicImage::from(get_preview(&args, result)).into_rgba8(),
+            0,
+            0,
+            *delay,
+        )
+    });
+    let mut encoder = GifEncoder::new(
+        File::create(format!("{}.preview.gif", input_file))
+            .expect("Unable to open preview output file."),
+    );
+    encoder
+        .set_repeat(Repeat::Infinite)
+        .expect("Unable to set repetition of preview animation.");
+    encoder
+        .encode_frames(preview_frames)
+        .expect("Unable to store preview animation.");
+}
+
+/// Load the frames (with their delays) of an animated GIF, an image sequence or a single image
+fn load_frames(input_files: &[String], frame_delay: u32) -> Vec<(DynamicImage, Delay)> {
+    let sequence_delay = Delay::from_numer_denom_ms(frame_delay, 1);
+    if let [input_file] = input_files {
+        let reader = ImageReader::open(input_file).expect("Unable to read image");
+        if reader.format() == Some(ImageFormat::Gif) {
+            let decoder = GifDecoder::new(BufReader::new(
+                File::open(input_file).expect("Unable to read image"),
+            ))
+            .expect("Unable to decode image");
+            return decoder
+                .into_frames()
+                .collect_frames()
+                .expect("Unable to decode image")
+                .into_iter()
+                .map(|frame| {
+                    let delay = frame.delay();
+                    (DynamicImage::from(frame.into_buffer()), delay)
+                })
+                .collect();
         }
     }
+
+    input_files
+        .iter()
+        .map(|input_file| {
+            let img = ImageReader::open(input_file)
+                .expect("Unable to read image")
+                .decode()
+                .expect("Unable to decode image");
+            (img, sequence_delay)
+        })
+        .collect()
+}
+
+fn get_preview(args: &Args, result: &ConversionResult) -> RgbImage {
+    if args.aspect_preview {
+        result.aspect_corrected_preview()
+    } else {
+        result.preview.clone()
+    }
+}
+
+fn print_information(args: &Args, result: &ConversionResult) {
+    if args.align_grid {
+        println!(
+            "Grid offset: {}, {}",
+            result.grid_offset.0, result.grid_offset.1
+        );
+    }
+    if args.compact_window {
+        println!(
+            "Window: {} lines, {} columns at offset {} (row stride {})",
+            result.window.lines,
+            result.window.columns,
+            result.window.row * result.row_stride + result.window.column,
+            result.row_stride
+        );
+    }
+
+    print_statistics(result);
+
+    for register in &result.registers {
+        println!(
+            "Register ${:04x}: ${:02x}",
+            register.address, register.value
+        );
+    }
+}
+
+fn write_images(args: &Args, result: &ConversionResult, prefix: &str) {
+    if let (true, Some(dithered)) = (args.save_dithered, &result.dithered) {
+        dithered
+            .save(format!("{}.dithered.png", prefix))
+            .expect("Unable to store dithered image.");
+    }
+
+    if args.heatmap {
+        result
+            .error_heatmap
+            .save(format!("{}.heatmap.png", prefix))
+            .expect("Unable to store heatmap image.");
+    }
+}
+
+fn write_file(file_name: &str, description: &str, content: &[u8]) {
+    File::create(file_name)
+        .unwrap_or_else(|_| panic!("Unable to open {} output file.", description))
+        .write_all(content)
+        .unwrap_or_else(|_| panic!("Unable to write to {} output file.", description));
+}
+
+fn write_binaries(result: &ConversionResult, prefix: &str) {
+    write_file(
+        &format!("{}.chars.bin", prefix),
+        "character ram",
+        result.character_ram.as_bytes(),
+    );
+
+    if let Some(color_ram) = &result.color_ram {
+        write_file(&format!("{}.color.bin", prefix), "color ram", color_ram);
+    }
+
+    if !result.registers.is_empty() {
+        // values of consecutive registers starting at the first address
+        let registers: Vec<u8> = result.registers.iter().map(|r| r.value).collect();
+        write_file(&format!("{}.registers.bin", prefix), "register", &registers);
+    }
+
+    if let Some(charset) = &result.charset {
+        write_file(&format!("{}.charset.bin", prefix), "charset", charset);
+    }
+}
+
+/// Write the outputs of all frames one after another and an index with the offset (in character
+/// and color RAM file, 32 bit little endian) and the delay (in milliseconds, 16 bit little
+/// endian) of each frame
+fn write_concatenated_binaries(
+    results: &[ConversionResult],
+    frames: &[(DynamicImage, Delay)],
+    prefix: &str,
+) {
+    let mut index = Vec::new();
+    let mut offset = 0;
+    for (result, (_, delay)) in results.iter().zip(frames) {
+        let (numerator, denominator) = delay.numer_denom_ms();
+        let delay = (numerator / denominator.max(1)).min(u16::MAX as u32) as u16;
+        index.extend((offset as u32).to_le_bytes());
+        index.extend(delay.to_le_bytes());
+        offset += result.character_ram.len();
+    }
+    write_file(&format!("{}.frames.bin", prefix), "frame index", &index);
+
+    let character_ram: Vec<u8> = results
+        .iter()
+        .flat_map(|result| result.character_ram.iter().copied())
+        .collect();
+    write_file(
+        &format!("{}.chars.bin", prefix),
+        "character ram",
+        &character_ram,
+    );
+
+    if results[0].color_ram.is_some() {
+        let color_ram: Vec<u8> = results
+            .iter()
+            .flat_map(|result| result.color_ram.iter().flatten().copied())
+            .collect();
+        write_file(&format!("{}.color.bin", prefix), "color ram", &color_ram);
+    }
+
+    if !results[0].registers.is_empty() {
+        let registers: Vec<u8> = results
+            .iter()
+            .flat_map(|result| result.registers.iter().map(|r| r.value))
+            .collect();
+        write_file(&format!("{}.registers.bin", prefix), "register", &registers);
+    }
+
+    if results[0].charset.is_some() {
+        let charsets: Vec<u8> = results
+            .iter()
+            .flat_map(|result| result.charset.iter().flatten().copied())
+            .collect();
+        write_file(&format!("{}.charset.bin", prefix), "charset", &charsets);
+    }
 }
@@ -1,42 +1,75 @@
-use crate::{profiles::MachineProfile, utils::image_diff};
+use crate::{
+    color::ColorDistance, glyph::Glyph, metric::TileMetric, profiles::MachineProfile,
+    utils::image_diff,
+};
 use image::{DynamicImage, GenericImage, Pixel, Rgb, RgbImage};
 use kmeans::{Cluster, KmeansContext};
 
 mod kmeans;
 
 /// Generate an optimized charset for representing the passed image
-pub fn generate_charset(profile: &ScreenProfile, img: &DynamicImage) -> Vec<RgbImage> {
-    let tiles = initialize_tiles(&profile, &img);
+pub fn generate_charset(
+    profile: &ScreenProfile,
+    img: &DynamicImage,
+    color_distance: ColorDistance,
+    tile_metric: Option<&dyn TileMetric>,
+    excluded_codes: &[u8],
+) -> Vec<Glyph> {
+    let tiles = initialize_tiles(profile, img);
 
     let context = CharsetGeneratorContext {
         character_width: profile.character_width,
         character_height: profile.character_height,
         _columns: profile.columns,
         _lines: profile.lines,
+        color_distance,
+        tile_metric,
     };
 
-    let clusters = kmeans::optimize(&context, profile.chars, &tiles, 50, 5);
+    let encoded_tiles = tiles
+        .iter()
+        .map(|tile| context.encode(tile))
+        .collect::<Vec<RgbImage>>();
+
+    let codes: Vec<usize> = (0..profile.chars)
+        .filter(|&code| !excluded_codes.contains(&(code as u8)))
+        .collect();
+
+    let clusters = kmeans::optimize(&context, codes.len(), &encoded_tiles, 50, 5);
 
     // context.draw_approximation("final.png", &clusters);
 
-    clusters
-        .iter()
-        .map(|cluster| cluster.centroid.clone())
-        .collect()
+    // centroids are determined from the original tiles since the encoded ones might not be RGB
+    let blank_glyph = Glyph::from_image(&RgbImage::new(
+        profile.character_width,
+        profile.character_height,
+    ));
+    let mut glyphs = vec![blank_glyph; profile.chars];
+    for (&code, cluster) in codes.iter().zip(&clusters) {
+        let elements = cluster
+            .elements
+            .iter()
+            .map(|assignment| &tiles[assignment.index])
+            .collect::<Vec<&RgbImage>>();
+        if !elements.is_empty() {
+            glyphs[code] = Glyph::from_image(&context.determine_colored_centroid(&elements));
+        }
+    }
+
+    // excluded codes keep a blank glyph
+    glyphs
 }
 
 /// Converts a charset that is represented as vector of images to memory representation as expected by the C64
-pub fn convert_charset(characters: &Vec<RgbImage>) -> Vec<u8> {
-    assert!(characters.len() == 256);
-    let mut data: Vec<u8> = Vec::with_capacity(255 * 8);
+pub fn convert_charset(characters: &[Glyph]) -> Vec<u8> {
+    assert!(characters.len() <= 256);
+    let mut data: Vec<u8> = Vec::with_capacity(characters.len() * 8);
     for character in characters {
         assert!(character.width() == 8 && character.height() == 8);
         for y in 0..8 {
             let mut byte: u8 = 0;
             for x in 0..8 {
-                let pixel = character.get_pixel(x, y);
-                let avg = (pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3;
-                if avg > 0x80 {
+                if character.is_set(x, y) {
                     byte |= 1 << (7 - x);
                 }
             }
@@ -46,15 +79,26 @@ pub fn convert_charset(characters: &Vec<RgbImage>) -> Vec<u8> {
     data
 }
 
-struct CharsetGeneratorContext {
+struct CharsetGeneratorContext<'a> {
     character_width: u32,
     character_height: u32,
     _columns: u32,
     _lines: u32,
+    color_distance: ColorDistance,
+    /// Used instead of the color distance for comparing tiles (on the original tiles) if set
+    tile_metric: Option<&'a dyn TileMetric>,
 }
 
-impl CharsetGeneratorContext {
-    fn _draw_approximation(&self, filename: &str, clusters: &Vec<Cluster<RgbImage>>) -> () {
+impl CharsetGeneratorContext<'_> {
+    /// Convert a tile into the representation that is compared by `diff`
+    fn encode(&self, img: &RgbImage) -> RgbImage {
+        match self.tile_metric {
+            Some(_) => img.clone(),
+            None => self.color_distance.encode_image(img),
+        }
+    }
+
+    fn _draw_approximation(&self, filename: &str, clusters: &[Cluster<RgbImage>]) {
         let mut approxed_img = RgbImage::new(
             self.character_width * self._columns,
             self.character_height * self._lines,
@@ -76,7 +120,7 @@ impl CharsetGeneratorContext {
             .expect("Unable to write approxed img");
     }
 
-    fn _determine_monochrome_centroid(&self, elements: &Vec<&RgbImage>) -> RgbImage {
+    fn _determine_monochrome_centroid(&self, elements: &[&RgbImage]) -> RgbImage {
         let mut img = RgbImage::new(self.character_width, self.character_height);
         for x in 0..self.character_width {
             for y in 0..self.character_height {
@@ -96,7 +140,7 @@ impl CharsetGeneratorContext {
         img
     }
 
-    fn determine_colored_centroid(&self, elements: &Vec<&RgbImage>) -> RgbImage {
+    fn determine_colored_centroid(&self, elements: &[&RgbImage]) -> RgbImage {
         let mut img = RgbImage::new(self.character_width, self.character_height);
 
         for x in 0..self.character_width {
@@ -104,17 +148,17 @@ impl CharsetGeneratorContext {
                 let r_avg = elements
                     .iter()
                     .map(|element| element.get_pixel(x, y).to_rgb()[0] as u32)
-                    .sum::<u32>() as u32
+                    .sum::<u32>()
                     / elements.len() as u32;
                 let g_avg = elements
                     .iter()
                     .map(|element| element.get_pixel(x, y).to_rgb()[1] as u32)
-                    .sum::<u32>() as u32
+                    .sum::<u32>()
                     / elements.len() as u32;
                 let b_avg = elements
                     .iter()
                     .map(|element| element.get_pixel(x, y).to_rgb()[2] as u32)
-                    .sum::<u32>() as u32
+                    .sum::<u32>()
                     / elements.len() as u32;
                 img.put_pixel(x, y, Rgb([r_avg as u8, g_avg as u8, b_avg as u8]));
             }
@@ -124,7 +168,7 @@ impl CharsetGeneratorContext {
     }
 }
 
-impl KmeansContext<RgbImage> for CharsetGeneratorContext {
+impl KmeansContext<RgbImage> for CharsetGeneratorContext<'_> {
     fn initialize_centroid(&self, k: usize) -> RgbImage {
         let mut rnd: i16 = 2144 + k as i16; // some reproducible pseudo-random pattern
         let mut img = RgbImage::new(self.character_width, self.character_height);
@@ -136,21 +180,24 @@ impl KmeansContext<RgbImage> for CharsetGeneratorContext {
                 img.put_pixel(x, y, Rgb([val, val, val]));
             }
         }
-        img
+        self.encode(&img)
     }
 
-    fn determine_centroid(&self, elements: &Vec<&RgbImage>) -> RgbImage {
+    fn determine_centroid(&self, elements: &[&RgbImage]) -> RgbImage {
         self.determine_colored_centroid(elements)
     }
 
-    fn iteration_callback(&self, _i: u32, _clusters: &Vec<Cluster<RgbImage>>) -> bool {
+    fn iteration_callback(&self, _i: u32, _clusters: &[Cluster<RgbImage>]) -> bool {
         // println!("Iteration: {}", i);
         // self.draw_approximation(format!("iteration{}.png", i).as_str(), clusters);
         false
     }
 
     fn diff(&self, a: &RgbImage, b: &RgbImage) -> u32 {
-        image_diff(a, b)
+        match self.tile_metric {
+            Some(tile_metric) => tile_metric.diff(a, b),
+            None => image_diff(a, b, |pa, pb| self.color_distance.encoded_diff(pa, pb)),
+        }
     }
 }
 
@@ -169,7 +216,7 @@ impl ScreenProfile {
             columns: profile.columns,
             character_width: profile.charset_definition.character_width,
             character_height: profile.charset_definition.character_height,
-            chars: 0x100,
+            chars: profile.color_mode.usable_codes(),
         }
     }
 }
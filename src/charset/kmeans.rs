@@ -1,11 +1,9 @@
-use std::u32;
-
 pub trait KmeansContext<T> {
     /// Initialize a centroid for a cluster
     fn initialize_centroid(&self, k: usize) -> T;
 
     /// Determine centroid for cluster by set of elements
-    fn determine_centroid(&self, elements: &Vec<&T>) -> T;
+    fn determine_centroid(&self, elements: &[&T]) -> T;
 
     /// Calculate difference between two elements
     ///
@@ -15,7 +13,7 @@ pub trait KmeansContext<T> {
     /// Called for each iteration
     ///
     /// If true is returned it means a stop criteria was reached and the main optimization loop should exit.
-    fn iteration_callback(&self, i: u32, clusters: &Vec<Cluster<T>>) -> bool;
+    fn iteration_callback(&self, i: u32, clusters: &[Cluster<T>]) -> bool;
 }
 
 pub struct Assignment {
@@ -36,20 +34,20 @@ struct Diff {
 pub fn optimize<T>(
     context: &impl KmeansContext<T>,
     k: usize,
-    elements: &Vec<T>,
+    elements: &[T],
     max_iterations: u32,
     fill_empty_clusters_every_nth_iteration: u32,
 ) -> Vec<Cluster<T>> {
     let mut clusters = initialize_clusters(context, k);
     for i in 0..max_iterations {
         reset_cluster_assignments(&mut clusters);
-        reassign_elements(context, &mut clusters, &elements);
-        recalculate_centroids(context, &mut clusters, &elements);
-        if i % fill_empty_clusters_every_nth_iteration == 0 {
-            if fill_empty_clusters(context, &mut clusters, &elements) {
-                // perfect approximation reached
-                return clusters;
-            }
+        reassign_elements(context, &mut clusters, elements);
+        recalculate_centroids(context, &mut clusters, elements);
+        if i % fill_empty_clusters_every_nth_iteration == 0
+            && fill_empty_clusters(context, &mut clusters, elements)
+        {
+            // perfect approximation reached
+            return clusters;
         }
         if context.iteration_callback(i, &clusters) {
             return clusters;
@@ -78,14 +76,14 @@ fn reset_cluster_assignments<T>(clusters: &mut Vec<Cluster<T>>) {
 
 fn reassign_elements<T>(
     context: &impl KmeansContext<T>,
-    clusters: &mut Vec<Cluster<T>>,
-    elements: &Vec<T>,
+    clusters: &mut [Cluster<T>],
+    elements: &[T],
 ) {
     for (index, element) in elements.iter().enumerate() {
         let mut best_cluster_idx: Option<usize> = None;
         let mut best_diff: u32 = u32::MAX;
-        for j in 0..clusters.len() {
-            let diff = context.diff(&clusters[j].centroid, &element);
+        for (j, cluster) in clusters.iter().enumerate() {
+            let diff = context.diff(&cluster.centroid, element);
             if diff < best_diff {
                 best_cluster_idx = Some(j);
                 best_diff = diff;
@@ -102,19 +100,19 @@ fn reassign_elements<T>(
 
 fn recalculate_centroids<T>(
     context: &impl KmeansContext<T>,
-    clusters: &mut Vec<Cluster<T>>,
-    elements: &Vec<T>,
+    clusters: &mut [Cluster<T>],
+    elements: &[T],
 ) {
     for cluster in clusters {
         if !cluster.elements.is_empty() {
-            cluster.centroid = recalculate_centroid(&cluster.elements, &elements, context);
+            cluster.centroid = recalculate_centroid(&cluster.elements, elements, context);
         }
     }
 }
 
 fn recalculate_centroid<T>(
-    cluster_elements: &Vec<Assignment>,
-    elements: &Vec<T>,
+    cluster_elements: &[Assignment],
+    elements: &[T],
     context: &impl KmeansContext<T>,
 ) -> T {
     let element_refs = cluster_elements
@@ -127,10 +125,10 @@ fn recalculate_centroid<T>(
 
 fn fill_empty_clusters<T>(
     context: &impl KmeansContext<T>,
-    clusters: &mut Vec<Cluster<T>>,
-    elements: &Vec<T>,
+    clusters: &mut [Cluster<T>],
+    elements: &[T],
 ) -> bool {
-    let mut max_diffs = get_highest_deviations_by_cluster(&clusters);
+    let mut max_diffs = get_highest_deviations_by_cluster(clusters);
     if !max_diffs.is_empty() {
         if max_diffs[0].diff == 0 {
             return true;
@@ -156,7 +154,7 @@ fn fill_empty_clusters<T>(
     false
 }
 
-fn get_highest_deviations_by_cluster<T>(clusters: &Vec<Cluster<T>>) -> Vec<Diff> {
+fn get_highest_deviations_by_cluster<T>(clusters: &[Cluster<T>]) -> Vec<Diff> {
     let mut max_diffs: Vec<Diff> = Vec::new();
 
     for c in clusters {
@@ -174,7 +172,7 @@ fn get_highest_deviations_by_cluster<T>(clusters: &Vec<Cluster<T>>) -> Vec<Diff>
         });
     }
 
-    max_diffs.sort_by(|d1, d2| d2.diff.cmp(&d1.diff));
+    max_diffs.sort_by_key(|d| std::cmp::Reverse(d.diff));
 
     max_diffs
 }
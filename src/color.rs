@@ -0,0 +1,285 @@
+use std::{f32::consts::PI, str::FromStr};
+
+use image::{Rgb, RgbImage};
+
+use crate::utils::parse_identifier;
+
+/// Metric for the difference between two colors
+///
+/// All metrics are scaled so that black and white have a distance of 765 (like in `Rgb`).
+#[derive(Clone, Copy, Debug, Default)]
+pub enum ColorDistance {
+    /// Sum of the absolute differences of the sRGB channels
+    #[default]
+    Rgb,
+    /// Euclidean distance of the sRGB channels weighted by their contribution to luminance
+    WeightedRgb,
+    /// Euclidean distance in CIELAB color space (CIE76)
+    Cie76,
+    /// CIEDE2000 color difference
+    Ciede2000,
+}
+
+const COLOR_DISTANCE_IDENTIFIERS: &[(&str, ColorDistance)] = &[
+    ("rgb", ColorDistance::Rgb),
+    ("weighted-rgb", ColorDistance::WeightedRgb),
+    ("cie76", ColorDistance::Cie76),
+    ("ciede2000", ColorDistance::Ciede2000),
+];
+
+impl FromStr for ColorDistance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_identifier(s, COLOR_DISTANCE_IDENTIFIERS, "color distance")
+    }
+}
+
+impl ColorDistance {
+    pub fn diff(&self, a: &Rgb<u8>, b: &Rgb<u8>) -> u32 {
+        match self {
+            ColorDistance::Rgb => {
+                a[0].abs_diff(b[0]) as u32 + a[1].abs_diff(b[1]) as u32 + a[2].abs_diff(b[2]) as u32
+            }
+            ColorDistance::WeightedRgb => {
+                let dr = a[0] as f32 - b[0] as f32;
+                let dg = a[1] as f32 - b[1] as f32;
+                let db = a[2] as f32 - b[2] as f32;
+                let distance = (0.299 * dr * dr + 0.587 * dg * dg + 0.114 * db * db).sqrt();
+                (distance * 3.0).round() as u32
+            }
+            ColorDistance::Cie76 => {
+                let a = Lab::from_rgb(a);
+                let b = Lab::from_rgb(b);
+                let distance =
+                    ((a.l - b.l).powi(2) + (a.a - b.a).powi(2) + (a.b - b.b).powi(2)).sqrt();
+                (distance * 7.65).round() as u32
+            }
+            ColorDistance::Ciede2000 => {
+                (ciede2000(&Lab::from_rgb(a), &Lab::from_rgb(b)) * 7.65).round() as u32
+            }
+        }
+    }
+}
+
+impl ColorDistance {
+    /// Convert an image into a representation whose pixels can be compared by `encoded_diff`
+    ///
+    /// Converting the color space once per image is much faster than converting it on every
+    /// comparison. The CIELAB based metrics store L*a*b* values (scaled to fit into bytes) and
+    /// compare them by their euclidean distance, since evaluating CIEDE2000 is too expensive for
+    /// the amount of comparisons during charset generation.
+    pub fn encode_image(&self, img: &RgbImage) -> RgbImage {
+        match self {
+            ColorDistance::Rgb | ColorDistance::WeightedRgb => img.clone(),
+            ColorDistance::Cie76 | ColorDistance::Ciede2000 => {
+                RgbImage::from_fn(img.width(), img.height(), |x, y| {
+                    let lab = Lab::from_rgb(img.get_pixel(x, y));
+                    Rgb([
+                        (lab.l * 2.55).round().clamp(0.0, 255.0) as u8,
+                        (lab.a + 128.0).round().clamp(0.0, 255.0) as u8,
+                        (lab.b + 128.0).round().clamp(0.0, 255.0) as u8,
+                    ])
+                })
+            }
+        }
+    }
+
+    /// Difference between two pixels of images that were converted by `encode_image`
+    pub fn encoded_diff(&self, a: &Rgb<u8>, b: &Rgb<u8>) -> u32 {
+        match self {
+            ColorDistance::Rgb | ColorDistance::WeightedRgb => self.diff(a, b),
+            ColorDistance::Cie76 | ColorDistance::Ciede2000 => {
+                let dl = (a[0] as f32 - b[0] as f32) / 2.55;
+                let da = a[1] as f32 - b[1] as f32;
+                let db = a[2] as f32 - b[2] as f32;
+                ((dl * dl + da * da + db * db).sqrt() * 7.65).round() as u32
+            }
+        }
+    }
+}
+
+/// Color in CIELAB color space (D65 white point)
+struct Lab {
+    l: f32,
+    a: f32,
+    b: f32,
+}
+
+impl Lab {
+    fn from_rgb(rgb: &Rgb<u8>) -> Self {
+        let linearize = |c: u8| {
+            let c = c as f32 / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        let r = linearize(rgb[0]);
+        let g = linearize(rgb[1]);
+        let b = linearize(rgb[2]);
+
+        let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+        let f = |t: f32| {
+            if t > 0.008856 {
+                t.cbrt()
+            } else {
+                7.787 * t + 16.0 / 116.0
+            }
+        };
+        let fx = f(x);
+        let fy = f(y);
+        let fz = f(z);
+
+        Self {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+}
+
+/// CIEDE2000 color difference
+///
+/// http://www2.ece.rochester.edu/~gsharma/ciede2000/ciede2000noteCRNA.pdf
+fn ciede2000(lab1: &Lab, lab2: &Lab) -> f32 {
+    let pow25_7 = 25f32.powi(7);
+    let hue = |b: f32, a: f32| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+
+    let c1 = (lab1.a.powi(2) + lab1.b.powi(2)).sqrt();
+    let c2 = (lab2.a.powi(2) + lab2.b.powi(2)).sqrt();
+    let c_avg_pow7 = ((c1 + c2) / 2.0).powi(7);
+    let g = 0.5 * (1.0 - (c_avg_pow7 / (c_avg_pow7 + pow25_7)).sqrt());
+
+    let a1 = (1.0 + g) * lab1.a;
+    let a2 = (1.0 + g) * lab2.a;
+    let c1 = (a1.powi(2) + lab1.b.powi(2)).sqrt();
+    let c2 = (a2.powi(2) + lab2.b.powi(2)).sqrt();
+    let h1 = hue(lab1.b, a1);
+    let h2 = hue(lab2.b, a2);
+
+    let delta_l = lab2.l - lab1.l;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 - h1 > 180.0 {
+        h2 - h1 - 360.0
+    } else {
+        h2 - h1 + 360.0
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let l_avg = (lab1.l + lab2.l) / 2.0;
+    let c_avg = (c1 + c2) / 2.0;
+    let h_avg = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let cos_deg = |degrees: f32| (degrees * PI / 180.0).cos();
+    let t = 1.0 - 0.17 * cos_deg(h_avg - 30.0)
+        + 0.24 * cos_deg(2.0 * h_avg)
+        + 0.32 * cos_deg(3.0 * h_avg + 6.0)
+        - 0.20 * cos_deg(4.0 * h_avg - 63.0);
+    let delta_theta = 30.0 * (-((h_avg - 275.0) / 25.0).powi(2)).exp();
+    let c_avg_pow7 = c_avg.powi(7);
+    let r_c = 2.0 * (c_avg_pow7 / (c_avg_pow7 + pow25_7)).sqrt();
+    let s_l = 1.0 + 0.015 * (l_avg - 50.0).powi(2) / (20.0 + (l_avg - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_avg;
+    let s_h = 1.0 + 0.015 * c_avg * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    ((delta_l / s_l).powi(2)
+        + (delta_c / s_c).powi(2)
+        + (delta_h / s_h).powi(2)
+        + r_t * (delta_c / s_c) * (delta_h / s_h))
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ciede2000_matches_reference_data() {
+        // pairs 1, 2, 7 to 15, 17 and 25 of Sharma, Wu and Dalal's test data
+        let pairs = [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+            ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+            ([50.0, -1.0, 2.0], [50.0, 0.0, 0.0], 2.3669),
+            ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0009], 7.1792),
+            ([50.0, 2.49, -0.001], [50.0, -2.49, 0.001], 7.1792),
+            ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0011], 7.2195),
+            ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0012], 7.2195),
+            ([50.0, -0.001, 2.49], [50.0, 0.0009, -2.49], 4.8045),
+            ([50.0, -0.001, 2.49], [50.0, 0.001, -2.49], 4.8045),
+            ([50.0, -0.001, 2.49], [50.0, 0.0011, -2.49], 4.7461),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            (
+                [60.2574, -34.0099, 36.2677],
+                [60.4626, -34.1751, 39.4387],
+                1.2644,
+            ),
+        ];
+        let lab = |[l, a, b]: [f32; 3]| Lab { l, a, b };
+        for (lab1, lab2, expected) in pairs {
+            let delta_e = ciede2000(&lab(lab1), &lab(lab2));
+            assert!(
+                (delta_e - expected).abs() < 0.001,
+                "{:?} {:?}: {} instead of {}",
+                lab1,
+                lab2,
+                delta_e,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn lab_from_rgb() {
+        for (rgb, [l, a, b]) in [
+            ([0x00, 0x00, 0x00], [0.0, 0.0, 0.0]),
+            ([0xff, 0xff, 0xff], [100.0, 0.0, 0.0]),
+            ([0xff, 0x00, 0x00], [53.24, 80.09, 67.2]),
+            ([0x00, 0x00, 0xff], [32.3, 79.19, -107.86]),
+        ] {
+            let lab = Lab::from_rgb(&Rgb(rgb));
+            assert!(
+                (lab.l - l).abs() < 0.05 && (lab.a - a).abs() < 0.05 && (lab.b - b).abs() < 0.05,
+                "{:?}: {} {} {}",
+                rgb,
+                lab.l,
+                lab.a,
+                lab.b
+            );
+        }
+    }
+
+    #[test]
+    fn black_and_white_have_a_distance_of_765() {
+        for (_, color_distance) in COLOR_DISTANCE_IDENTIFIERS {
+            assert_eq!(
+                color_distance.diff(&Rgb([0x00, 0x00, 0x00]), &Rgb([0xff, 0xff, 0xff])),
+                765
+            );
+        }
+    }
+}
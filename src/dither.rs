@@ -0,0 +1,186 @@
+use std::str::FromStr;
+
+use image::{Rgb, RgbImage};
+
+use crate::{color::ColorDistance, matching::min_index, utils::parse_identifier};
+
+/// Algorithm for reducing an image to the colors of a palette
+#[derive(Clone, Copy, Debug)]
+pub enum Dithering {
+    /// Error diffusion distributing the whole error to four neighbors
+    FloydSteinberg,
+    /// Error diffusion distributing 3/4 of the error to six neighbors (more contrast, less noise)
+    Atkinson,
+    /// Ordered dithering using a 2x2 Bayer matrix
+    Bayer2,
+    /// Ordered dithering using a 4x4 Bayer matrix
+    Bayer4,
+    /// Ordered dithering using an 8x8 Bayer matrix
+    Bayer8,
+}
+
+const DITHERING_IDENTIFIERS: &[(&str, Dithering)] = &[
+    ("floyd-steinberg", Dithering::FloydSteinberg),
+    ("atkinson", Dithering::Atkinson),
+    ("bayer2", Dithering::Bayer2),
+    ("bayer4", Dithering::Bayer4),
+    ("bayer8", Dithering::Bayer8),
+];
+
+impl FromStr for Dithering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_identifier(s, DITHERING_IDENTIFIERS, "dithering")
+    }
+}
+
+/// Reduce an image to the colors of the passed palette
+pub fn dither(
+    img: &RgbImage,
+    palette: &[Rgb<u8>],
+    dithering: Dithering,
+    color_distance: ColorDistance,
+) -> RgbImage {
+    let ditherer = Ditherer {
+        palette,
+        color_distance,
+    };
+    match dithering {
+        Dithering::FloydSteinberg => ditherer.diffuse_error(
+            img,
+            &[
+                (1, 0, 7.0 / 16.0),
+                (-1, 1, 3.0 / 16.0),
+                (0, 1, 5.0 / 16.0),
+                (1, 1, 1.0 / 16.0),
+            ],
+        ),
+        Dithering::Atkinson => ditherer.diffuse_error(
+            img,
+            &[
+                (1, 0, 1.0 / 8.0),
+                (2, 0, 1.0 / 8.0),
+                (-1, 1, 1.0 / 8.0),
+                (0, 1, 1.0 / 8.0),
+                (1, 1, 1.0 / 8.0),
+                (0, 2, 1.0 / 8.0),
+            ],
+        ),
+        Dithering::Bayer2 => ditherer.ordered(img, 2),
+        Dithering::Bayer4 => ditherer.ordered(img, 4),
+        Dithering::Bayer8 => ditherer.ordered(img, 8),
+    }
+}
+
+struct Ditherer<'a> {
+    palette: &'a [Rgb<u8>],
+    color_distance: ColorDistance,
+}
+
+impl Ditherer<'_> {
+    fn nearest_color(&self, color: [f32; 3]) -> Rgb<u8> {
+        let pixel = Rgb(color.map(|value| value.round().clamp(0.0, 255.0) as u8));
+        let diffs: Vec<u32> = self
+            .palette
+            .iter()
+            .map(|palette_color| self.color_distance.diff(&pixel, palette_color))
+            .collect();
+
+        self.palette[min_index(&diffs).0 as usize]
+    }
+
+    /// Error diffusion dithering
+    ///
+    /// The kernel consists of x offset, y offset and the share of the error for each neighbor.
+    fn diffuse_error(&self, img: &RgbImage, kernel: &[(i64, i64, f32)]) -> RgbImage {
+        let width = img.width() as i64;
+        let height = img.height() as i64;
+        let mut values: Vec<[f32; 3]> = img.pixels().map(|pixel| pixel.0.map(f32::from)).collect();
+        let mut dithered = RgbImage::new(img.width(), img.height());
+        for y in 0..height {
+            for x in 0..width {
+                let value = values[(y * width + x) as usize];
+                let color = self.nearest_color(value);
+                dithered.put_pixel(x as u32, y as u32, color);
+                for &(x_offset, y_offset, share) in kernel {
+                    let (neighbor_x, neighbor_y) = (x + x_offset, y + y_offset);
+                    if neighbor_x < 0 || neighbor_x >= width || neighbor_y >= height {
+                        continue;
+                    }
+                    let neighbor = &mut values[(neighbor_y * width + neighbor_x) as usize];
+                    for channel in 0..3 {
+                        neighbor[channel] += (value[channel] - color[channel] as f32) * share;
+                    }
+                }
+            }
+        }
+
+        dithered
+    }
+
+    /// Ordered dithering using a Bayer matrix of the passed size
+    ///
+    /// Since the palette colors are not evenly distributed, a threshold can't simply be added to
+    /// the pixels. Instead each pixel gets approximated by a mix of palette colors (Thomas Knoll's
+    /// pattern dithering), which are sorted by luminance and selected by the matrix value.
+    fn ordered(&self, img: &RgbImage, size: u32) -> RgbImage {
+        let matrix = bayer_matrix(size);
+        let mix_size = (size * size).min(16) as usize;
+        RgbImage::from_fn(img.width(), img.height(), |x, y| {
+            let value = img.get_pixel(x, y).0.map(f32::from);
+            let mut mix = Vec::with_capacity(mix_size);
+            let mut error = [0.0; 3];
+            for _ in 0..mix_size {
+                let color = self.nearest_color([0, 1, 2].map(|c| value[c] + error[c]));
+                for channel in 0..3 {
+                    error[channel] += value[channel] - color[channel] as f32;
+                }
+                mix.push(color);
+            }
+            mix.sort_by_key(|color| {
+                299 * color[0] as u32 + 587 * color[1] as u32 + 114 * color[2] as u32
+            });
+            let threshold = matrix[((y % size) * size + x % size) as usize];
+
+            mix[threshold as usize * mix_size / (size * size) as usize]
+        })
+    }
+}
+
+/// Bayer threshold matrix (values 0 to size² - 1, row by row) for a power of two size
+fn bayer_matrix(size: u32) -> Vec<u32> {
+    if size == 1 {
+        return vec![0];
+    }
+    let half = size / 2;
+    let smaller = bayer_matrix(half);
+    let mut matrix = vec![0; (size * size) as usize];
+    for y in 0..size {
+        for x in 0..size {
+            let quadrant = [0, 2, 3, 1][((y / half) * 2 + x / half) as usize];
+            matrix[(y * size + x) as usize] =
+                4 * smaller[((y % half) * half + x % half) as usize] + quadrant;
+        }
+    }
+
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bayer_matrices() {
+        assert_eq!(bayer_matrix(2), vec![0, 2, 3, 1]);
+        assert_eq!(
+            bayer_matrix(4),
+            vec![0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5]
+        );
+
+        let mut values = bayer_matrix(8);
+        values.sort();
+        assert_eq!(values, (0..64).collect::<Vec<u32>>());
+    }
+}
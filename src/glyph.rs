@@ -0,0 +1,70 @@
+use image::{Rgb, RgbImage};
+
+use crate::utils::is_pixel_set;
+
+/// Monochrome representation of a character
+///
+/// Pixels are either set (drawn in the foreground color) or unset (drawn in the background color).
+/// The actual colors are only applied when the glyph gets rendered.
+#[derive(Clone)]
+pub struct Glyph {
+    width: u32,
+    height: u32,
+    pixels: Vec<bool>,
+}
+
+impl Glyph {
+    /// Create a glyph from an image by treating bright pixels as set
+    pub fn from_image(img: &RgbImage) -> Self {
+        Self {
+            width: img.width(),
+            height: img.height(),
+            pixels: img.pixels().map(is_pixel_set).collect(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn is_set(&self, x: u32, y: u32) -> bool {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Iterate over all pixels row by row (the same order as `RgbImage::pixels`)
+    pub fn pixels(&self) -> impl Iterator<Item = bool> + '_ {
+        self.pixels.iter().copied()
+    }
+
+    pub fn invert(&mut self) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = !*pixel;
+        }
+    }
+
+    /// Render the glyph using the passed foreground and background colors
+    pub fn render(&self, foreground: Rgb<u8>, background: Rgb<u8>) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            if self.is_set(x, y) {
+                foreground
+            } else {
+                background
+            }
+        })
+    }
+
+    /// Render the glyph in multicolor mode
+    ///
+    /// Each pair of horizontally adjacent pixels forms a double-wide pixel whose two bits select
+    /// one of the four passed colors.
+    pub fn render_multicolor(&self, colors: [Rgb<u8>; 4]) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let x = x & !1;
+            colors[(self.is_set(x, y) as usize) << 1 | self.is_set(x + 1, y) as usize]
+        })
+    }
+}
@@ -1,35 +1,464 @@
+use std::{collections::HashSet, time::Duration};
+
 use charset::{convert_charset, generate_charset, ScreenProfile};
-use image::{DynamicImage, GenericImage, RgbImage};
-use profiles::{Character, MachineProfile, AVAILABLE_PROFILES};
-use utils::{image_diff, load_charset};
+use dither::dither;
+use glyph::Glyph;
+use image::{imageops, DynamicImage, GenericImage, GenericImageView, Rgb, RgbImage};
+use matching::{
+    get_color_diffs, min_candidate, min_index, HiresGlyphDiffs, MulticolorGlyphDiffs, TileDiffs,
+};
+use profiles::{Character, ColorMode, MachineProfile, AVAILABLE_PROFILES};
+use scaling::to_square_pixels;
+use statistics::{create_error_heatmap, get_cell_errors, get_psnr};
+use transparency::get_alpha_mask;
+use utils::{image_diff, is_pixel_set, load_charset, mean_color, shift_colors};
 
 mod charset;
+mod color;
+mod dither;
+mod glyph;
+mod matching;
+mod metric;
+mod optimization;
+mod preprocessing;
 mod profiles;
+mod scaling;
+mod statistics;
+mod transparency;
 mod utils;
 
+pub use color::ColorDistance;
+pub use dither::Dithering;
+pub use metric::{
+    BlurredError, MeanAbsoluteError, SquaredError, StructuralError, TileMetric, TileMetricKind,
+};
+pub use preprocessing::{Preprocessing, UnsharpMask};
+pub use scaling::{FitMode, Scaling};
+pub use transparency::Transparency;
+
 pub struct ConversionResult {
     pub preview: RgbImage,
     pub character_ram: Vec<u8>,
     pub color_ram: Option<Vec<u8>>,
     pub charset: Option<Vec<u8>>,
+    /// Values for the machine's registers that hold the border color and the colors shared by
+    /// all cells (ordered by address)
+    pub registers: Vec<Register>,
+    /// Input image reduced to the machine's palette (if dithering was used)
+    pub dithered: Option<RgbImage>,
+    /// Number of character columns of the screen
+    pub columns: u32,
+    /// Number of different glyphs (combinations of charset and code) used by the cells
+    pub distinct_codes: usize,
+    /// Mean color distance between the pixels of the input image and the preview for each cell
+    /// (row by row)
+    pub cell_errors: Vec<u32>,
+    /// Image that shows the error of each cell (black: no error, white: large error)
+    pub error_heatmap: RgbImage,
+    /// Peak signal-to-noise ratio (in dB) of the preview compared to the input image (`None` if
+    /// the preview is identical to the image)
+    pub psnr: Option<f64>,
+    /// Result of the whole-image optimization (if it was used)
+    pub optimization: Option<OptimizationReport>,
+    /// Number of pixels that were skipped at the left and top of the image to align the cells
+    /// with the image
+    pub grid_offset: (u32, u32),
+    /// Adjustments that were applied to the image before converting it
+    pub preprocessing: Preprocessing,
+    /// Width of a pixel relative to its height as displayed by the machine
+    pub pixel_aspect_ratio: f32,
+    /// Area of the screen the image was converted into
+    pub window: Window,
+    /// Number of bytes between the starts of two rows of the machine's screen (character and
+    /// color RAM), for copying the rows of a compact window to the screen
+    pub row_stride: u32,
+}
+
+impl ConversionResult {
+    /// Preview scaled to the pixel aspect ratio of the machine (as it appears on its display)
+    pub fn aspect_corrected_preview(&self) -> RgbImage {
+        to_square_pixels(&self.preview, self.pixel_aspect_ratio)
+    }
+}
+
+/// Mean error per pixel between the blurred input image and the blurred preview before and after
+/// the whole-image optimization
+pub struct OptimizationReport {
+    pub initial_error: f64,
+    pub final_error: f64,
+}
+
+pub struct Register {
+    pub address: u16,
+    pub value: u8,
+}
+
+#[derive(Default)]
+pub struct ConversionOptions<'a> {
+    /// Generate an optimized charset for the image instead of using the machine's charset
+    pub generate_charset: bool,
+    /// Image of the same dimension as the input image whose bright areas mark blinking cells
+    ///
+    /// It is cropped, scaled and offset together with the input image.
+    pub blink_mask: Option<&'a DynamicImage>,
+    /// Metric for comparing the colors of the image with the colors of the machine
+    pub color_distance: ColorDistance,
+    /// Metric for comparing tiles of the image with rendered characters
+    ///
+    /// If not set, the mean of the per pixel color distances is used, which allows a much faster
+    /// matching that doesn't need to render each candidate character.
+    pub tile_metric: Option<&'a dyn TileMetric>,
+    /// Match characters by comparing gaussian blurred tiles (including one pixel of the
+    /// neighboring cells) using the passed standard deviation
+    ///
+    /// Can't be combined with `tile_metric`.
+    pub low_pass_sigma: Option<f32>,
+    /// Distribute the color error of each cell to the neighboring cells before matching them
+    /// (Floyd-Steinberg error diffusion at cell granularity)
+    pub error_diffusion: bool,
+    /// Dither the image to the machine's palette before matching the characters
+    pub dithering: Option<Dithering>,
+    /// Character codes that must not be used (in addition to the ones excluded by the profile)
+    pub excluded_codes: &'a [u8],
+    /// Texts that are written into the screen using the machine's charset
+    ///
+    /// The covered cells are not matched against the image.
+    pub text_overlays: &'a [TextOverlay],
+    /// Improve the character assignment for the whole screen (instead of each cell on its own)
+    /// for the passed duration
+    pub optimization_time: Option<Duration>,
+    /// Search the horizontal and vertical offset (less than a character's dimension) at which the
+    /// cells match the image best and convert the image at that offset
+    ///
+    /// The image needs to be larger than the screen for offsets other than 0.
+    pub align_grid: bool,
+    /// Adjustments of the image before it gets converted
+    pub preprocessing: Preprocessing,
+    /// Scaling of the image to the dimension of the screen (applied before the preprocessing)
+    pub scaling: Scaling,
+    /// Width of a pixel relative to its height (overrides the value of the profile, for displays
+    /// that differ from the usual setup)
+    pub pixel_aspect_ratio: Option<f32>,
+    /// Region of the image to use (applied before scaling)
+    pub crop: Option<Crop>,
+    /// Position of the top left corner of the screen in the (scaled) image
+    pub offset: (u32, u32),
+    /// Handling of transparent pixels (by default the alpha channel is ignored)
+    pub transparency: Option<Transparency>,
+    /// Area of the screen to convert the image into (the whole screen by default)
+    ///
+    /// Text overlays are positioned relative to the window.
+    pub window: Option<Window>,
+    /// Only output the window's cells in character and color RAM instead of a whole screen where
+    /// the cells outside of the window are filled with spaces
+    pub compact_window: bool,
+}
+
+/// Rectangular area of the screen (in cells)
+#[derive(Clone, Copy, Debug)]
+pub struct Window {
+    pub row: u32,
+    pub column: u32,
+    pub lines: u32,
+    pub columns: u32,
+}
+
+impl Window {
+    /// Window that covers the whole screen
+    fn screen(profile: &MachineProfile) -> Self {
+        Self {
+            row: 0,
+            column: 0,
+            lines: profile.lines,
+            columns: profile.columns,
+        }
+    }
+
+    fn check(&self, profile: &MachineProfile) -> Result<(), String> {
+        if self.lines == 0
+            || self.columns == 0
+            || self
+                .row
+                .checked_add(self.lines)
+                .is_none_or(|end| end > profile.lines)
+            || self
+                .column
+                .checked_add(self.columns)
+                .is_none_or(|end| end > profile.columns)
+        {
+            return Err(format!(
+                "Window at row {}, column {} with {} lines and {} columns exceeds the screen of {} lines and {} columns.",
+                self.row, self.column, self.lines, self.columns, profile.lines, profile.columns
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Rectangular region of an image
+#[derive(Clone, Copy, Debug)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Crop {
+    fn apply(&self, img: &DynamicImage) -> Result<DynamicImage, String> {
+        if self.width == 0
+            || self.height == 0
+            || self
+                .x
+                .checked_add(self.width)
+                .is_none_or(|right| right > img.width())
+            || self
+                .y
+                .checked_add(self.height)
+                .is_none_or(|bottom| bottom > img.height())
+        {
+            return Err(format!(
+                "Crop region {},{} ({}x{} pixels) exceeds the image size of {}x{} pixels.",
+                self.x,
+                self.y,
+                self.width,
+                self.height,
+                img.width(),
+                img.height()
+            ));
+        }
+
+        Ok(img.crop_imm(self.x, self.y, self.width, self.height))
+    }
+}
+
+/// Skip the pixels left of and above the offset
+fn apply_offset(
+    img: &DynamicImage,
+    (x, y): (u32, u32),
+    screen_width: u32,
+    screen_height: u32,
+) -> Result<DynamicImage, String> {
+    if x == 0 && y == 0 {
+        return Ok(img.clone());
+    }
+    if x.checked_add(screen_width)
+        .is_none_or(|right| right > img.width())
+        || y.checked_add(screen_height)
+            .is_none_or(|bottom| bottom > img.height())
+    {
+        return Err(format!(
+            "Screen region {},{} ({}x{} pixels) exceeds the image size of {}x{} pixels.",
+            x,
+            y,
+            screen_width,
+            screen_height,
+            img.width(),
+            img.height()
+        ));
+    }
+
+    Ok(img.crop_imm(x, y, img.width() - x, img.height() - y))
+}
+
+/// Text that is written into the screen starting at the passed cell
+pub struct TextOverlay {
+    pub row: u32,
+    pub column: u32,
+    pub text: String,
+    /// Palette index of the text color
+    pub foreground: Option<u8>,
+    /// Palette index of the background color (only used if the machine supports a background
+    /// color per cell)
+    pub background: Option<u8>,
 }
 
 pub fn convert(
     input_img: &DynamicImage,
     profile_identifier: &str,
-    autogenerate_charset: bool,
+    options: &ConversionOptions,
 ) -> Result<ConversionResult, String> {
     for profile in AVAILABLE_PROFILES {
         if profile.identifier == profile_identifier {
-            let charset = if autogenerate_charset {
-                let charset =
-                    generate_charset(&ScreenProfile::from_machine_profile(profile), input_img);
+            let pixel_aspect_ratio = options
+                .pixel_aspect_ratio
+                .unwrap_or(profile.pixel_aspect_ratio);
+            if pixel_aspect_ratio <= 0.0 || !pixel_aspect_ratio.is_finite() {
+                return Err(format!(
+                    "Pixel aspect ratio {} must be a positive number.",
+                    pixel_aspect_ratio
+                ));
+            }
+            if let Some(sigma) = options
+                .low_pass_sigma
+                .filter(|sigma| *sigma <= 0.0 || !sigma.is_finite())
+            {
+                return Err(format!(
+                    "Low-pass sigma {} must be a positive number.",
+                    sigma
+                ));
+            }
+            let window = match options.window {
+                Some(window) => {
+                    window.check(profile)?;
+                    window
+                }
+                None => Window::screen(profile),
+            };
+            // the image is converted into the window, so "screen" refers to it from here on
+            let screen_width = window.columns * profile.charset_definition.character_width;
+            let screen_height = window.lines * profile.charset_definition.character_height;
+            // the masks take the same way as the image
+            let fit_to_screen = |img: &DynamicImage, scaling: &Scaling| {
+                let img = match options.crop {
+                    Some(crop) => crop.apply(img)?,
+                    None => img.clone(),
+                };
+                let img = scaling.apply(&img, screen_width, screen_height, pixel_aspect_ratio);
+                apply_offset(&img, options.offset, screen_width, screen_height)
+            };
+            // areas added by scaling count as opaque
+            let alpha_mask = match options.transparency {
+                Some(Transparency {
+                    fill_code: Some(_), ..
+                }) => Some(fit_to_screen(
+                    &get_alpha_mask(input_img),
+                    &Scaling {
+                        fill_color: Rgb([0xff, 0xff, 0xff]),
+                        ..options.scaling
+                    },
+                )?),
+                _ => None,
+            };
+            // areas added by scaling don't blink
+            let blink_mask = options
+                .blink_mask
+                .map(|blink_mask| {
+                    fit_to_screen(
+                        blink_mask,
+                        &Scaling {
+                            fill_color: Rgb([0x00, 0x00, 0x00]),
+                            ..options.scaling
+                        },
+                    )
+                })
+                .transpose()?;
+            let input_img = &match options.transparency {
+                Some(transparency) => {
+                    fit_to_screen(&transparency.composite(input_img), &options.scaling)?
+                }
+                None => fit_to_screen(input_img, &options.scaling)?,
+            };
+            let input_img = &options.preprocessing.apply(input_img)?;
+            let dithered = options.dithering.map(|dithering| {
+                DynamicImage::from(dither(
+                    &input_img.to_rgb8(),
+                    profile.palette,
+                    dithering,
+                    options.color_distance,
+                ))
+            });
+            let excluded_codes: Vec<u8> = profile
+                .excluded_codes
+                .iter()
+                .chain(options.excluded_codes)
+                .copied()
+                .collect();
+            if (0..profile.color_mode.usable_codes())
+                .all(|code| excluded_codes.contains(&(code as u8)))
+            {
+                return Err("All character codes are excluded.".to_string());
+            }
+
+            // The alignment is determined using the machine's charset since a generated charset
+            // depends on the alignment.
+            let grid_offset = if options.align_grid {
+                Converter::new(profile, None, &excluded_codes, options)
+                    .find_grid_offset(dithered.as_ref().unwrap_or(input_img))?
+            } else {
+                (0, 0)
+            };
+            let align = |img: &DynamicImage| {
+                img.crop_imm(
+                    grid_offset.0,
+                    grid_offset.1,
+                    img.width().saturating_sub(grid_offset.0),
+                    img.height().saturating_sub(grid_offset.1),
+                )
+            };
+            let original_img = &align(input_img);
+            let input_img = &align(dithered.as_ref().unwrap_or(input_img));
+            let blink_mask = blink_mask.as_ref().map(align);
+            let alpha_mask = alpha_mask.as_ref().map(align);
+
+            let charset = if options.generate_charset {
+                let charset = generate_charset(
+                    &ScreenProfile {
+                        lines: window.lines,
+                        columns: window.columns,
+                        ..ScreenProfile::from_machine_profile(profile)
+                    },
+                    input_img,
+                    options.color_distance,
+                    options.tile_metric,
+                    &excluded_codes,
+                );
                 Some(charset)
             } else {
                 None
             };
 
-            return Converter::new(profile, charset).convert(input_img);
+            let converter = Converter::new(profile, charset, &excluded_codes, options);
+            let conversion =
+                converter.convert(input_img, blink_mask.as_ref(), alpha_mask.as_ref())?;
+            let characters = &conversion.characters;
+            let preview = converter.create_preview(characters, &conversion.global_colors);
+
+            // statistics refer to the preprocessed image (not the dithered one)
+            let cell_width = profile.charset_definition.character_width;
+            let cell_height = profile.charset_definition.character_height;
+            let cell_errors = get_cell_errors(
+                original_img,
+                &preview,
+                cell_width,
+                cell_height,
+                options.color_distance,
+            );
+
+            return Ok(ConversionResult {
+                character_ram: converter
+                    .map_character_ram(characters, &conversion.global_colors)?,
+                color_ram: converter.map_color_ram(characters, &conversion.global_colors)?,
+                charset: if converter.uses_custom_charset {
+                    Some(convert_charset(&converter.charsets[0]))
+                } else {
+                    None
+                },
+                registers: converter.map_registers(&conversion.global_colors, input_img),
+                dithered: dithered.map(DynamicImage::into_rgb8),
+                columns: window.columns,
+                distinct_codes: characters
+                    .iter()
+                    .map(|character| (character.charset, character.code))
+                    .collect::<HashSet<_>>()
+                    .len(),
+                error_heatmap: create_error_heatmap(
+                    &cell_errors,
+                    window.columns,
+                    cell_width,
+                    cell_height,
+                ),
+                cell_errors,
+                psnr: get_psnr(original_img, &preview),
+                preview,
+                optimization: conversion.optimization,
+                grid_offset,
+                preprocessing: options.preprocessing.clone(),
+                pixel_aspect_ratio,
+                window,
+                row_stride: profile.columns,
+            });
         }
     }
 
@@ -39,22 +468,42 @@ pub fn convert(
         .collect::<Vec<&str>>()
         .join(", ");
 
-    return Err(format!(
+    Err(format!(
         "Unknown profile identifier \"{}\".\nAvailable profiles: {}",
         profile_identifier, identifiers
-    ));
+    ))
+}
+
+/// Characters and global colors determined by the converter
+struct Conversion {
+    characters: Vec<Character>,
+    global_colors: Vec<u8>,
+    optimization: Option<OptimizationReport>,
 }
 
 struct Converter<'a> {
     profile: &'a MachineProfile,
-    charsets: Vec<Vec<RgbImage>>,
+    options: &'a ConversionOptions<'a>,
+    charsets: Vec<Vec<Glyph>>,
     uses_custom_charset: bool,
+    /// Whether a code must not be used (indexed by code)
+    excluded_codes: Vec<bool>,
+    /// Dimension of the converted area (the window) in pixels
     screen_height: u32,
     screen_width: u32,
+    /// Dimension of the converted area in cells
+    lines: u32,
+    columns: u32,
+    window: Window,
 }
 
 impl<'a> Converter<'a> {
-    fn new(profile: &'a MachineProfile, custom_charset: Option<Vec<RgbImage>>) -> Self {
+    fn new(
+        profile: &'a MachineProfile,
+        custom_charset: Option<Vec<Glyph>>,
+        excluded_codes: &[u8],
+        options: &'a ConversionOptions<'a>,
+    ) -> Self {
         let uses_custom_charset = custom_charset.is_some();
         let charsets = {
             if let Some(custom_charset) = custom_charset {
@@ -71,82 +520,705 @@ impl<'a> Converter<'a> {
             }
         };
 
+        let mut excluded = vec![false; 0x100];
+        for &code in excluded_codes {
+            excluded[code as usize] = true;
+        }
+
+        let window = options.window.unwrap_or(Window::screen(profile));
+
         Self {
             charsets,
             uses_custom_charset,
-            screen_height: profile.lines * profile.charset_definition.character_height,
-            screen_width: profile.columns * profile.charset_definition.character_width,
-            profile: profile,
+            excluded_codes: excluded,
+            screen_height: window.lines * profile.charset_definition.character_height,
+            screen_width: window.columns * profile.charset_definition.character_width,
+            lines: window.lines,
+            columns: window.columns,
+            window,
+            profile,
+            options,
         }
     }
 
-    fn convert(&self, input_img: &DynamicImage) -> Result<crate::ConversionResult, String> {
+    fn convert(
+        &self,
+        input_img: &DynamicImage,
+        blink_mask: Option<&DynamicImage>,
+        alpha_mask: Option<&DynamicImage>,
+    ) -> Result<Conversion, String> {
         if input_img.width() < self.screen_width || input_img.height() < self.screen_height {
             return Err(format!(
                 "Input file must have a dimension of at least {}x{} pixels.",
                 self.screen_width, self.screen_height
             ));
         }
+        if self.options.low_pass_sigma.is_some() && self.options.tile_metric.is_some() {
+            return Err("Low-pass matching can't be combined with a tile metric.".to_string());
+        }
 
-        let mut characters: Vec<Character> = Vec::new();
+        let mut tile_imgs: Vec<RgbImage> = Vec::new();
 
-        for row in 0..self.profile.lines {
-            for column in 0..self.profile.columns {
-                let tile = input_img
-                    .crop_imm(
-                        column * self.profile.charset_definition.character_width,
-                        row * self.profile.charset_definition.character_height,
-                        self.profile.charset_definition.character_width,
-                        self.profile.charset_definition.character_height,
-                    )
-                    .into_rgb8();
-                characters.push(self.get_best_matching_character(&tile));
+        for row in 0..self.lines {
+            for column in 0..self.columns {
+                tile_imgs.push(self.get_tile(input_img, row, column));
             }
         }
 
-        Ok(ConversionResult {
-            preview: self.create_preview(&characters),
-            character_ram: self.map_character_ram(&characters),
-            color_ram: self.map_color_ram(&characters),
-            charset: if self.uses_custom_charset {
-                Some(convert_charset(&self.charsets[0]))
+        let overlay_codes = self.get_overlay_codes()?;
+        let fill_code = self.get_fill_code()?;
+        let transparent_cells: Vec<bool> = (0..tile_imgs.len() as u32)
+            .map(|i| match alpha_mask {
+                Some(alpha_mask) => self
+                    .get_tile(alpha_mask, i / self.columns, i % self.columns)
+                    .pixels()
+                    .all(|pixel| pixel[0] == 0),
+                None => false,
+            })
+            .collect();
+
+        // no need to compare cells that are covered by text overlays or transparent
+        let matched_cells: Vec<bool> = overlay_codes
+            .iter()
+            .zip(&transparent_cells)
+            .map(|(overlay_code, transparent)| overlay_code.is_none() && !transparent)
+            .collect();
+        // With error diffusion the cells are matched using their shifted colors, so the diffs of
+        // the unchanged tiles are only needed for the global colors and the optimization.
+        let needs_tile_diffs = !self.options.error_diffusion
+            || self.options.optimization_time.is_some()
+            || !matches!(
+                self.profile.color_mode,
+                ColorMode::Fixed { .. } | ColorMode::ForegroundBackground
+            );
+        let tiles: Vec<Option<TileDiffs>> = tile_imgs
+            .iter()
+            .zip(&matched_cells)
+            .map(|(tile_img, &matched)| {
+                (matched && needs_tile_diffs).then(|| self.get_tile_diffs(tile_img))
+            })
+            .collect();
+
+        // The global colors are always optimized using the per pixel color distances since
+        // rendering all candidates for every tried color combination would be too slow.
+        let global_colors =
+            self.determine_global_colors(&tiles.iter().flatten().collect::<Vec<_>>(), input_img);
+
+        let mut characters: Vec<Character> = Vec::new();
+        // accumulated color error of the already matched neighbors of each cell
+        let mut errors = vec![[0.0; 3]; tiles.len()];
+        for (i, (tile_img, tile)) in tile_imgs.iter().zip(&tiles).enumerate() {
+            let row = i as u32 / self.columns;
+            let column = i as u32 - row * self.columns;
+            if !matched_cells[i] {
+                let character = match overlay_codes[i] {
+                    Some((code, overlay)) => self.get_fixed_character(
+                        code,
+                        overlay.foreground,
+                        overlay.background,
+                        &global_colors,
+                    )?,
+                    None => self.get_fixed_character(
+                        fill_code.expect("Opaque cell without overlay is not matched"),
+                        None,
+                        None,
+                        &global_colors,
+                    )?,
+                };
+                characters.push(character);
+                continue;
+            }
+            let context_img = self
+                .options
+                .low_pass_sigma
+                .map(|_| shift_colors(&self.get_context_tile(input_img, row, column), errors[i]));
+            let character = if self.options.error_diffusion {
+                let tile_img = shift_colors(tile_img, errors[i]);
+                let character = self.determine_character(
+                    &tile_img,
+                    &self.get_tile_diffs(&tile_img),
+                    context_img.as_ref(),
+                    &global_colors,
+                );
+                let tile_color = mean_color(&tile_img);
+                let character_color =
+                    mean_color(&self.render_character(&character, &global_colors));
+                self.diffuse_error(
+                    &mut errors,
+                    row,
+                    column,
+                    [0, 1, 2].map(|c| tile_color[c] - character_color[c]),
+                );
+                character
             } else {
-                None
+                let tile = tile
+                    .as_ref()
+                    .expect("Tile diffs are computed for matched cells without error diffusion");
+                self.determine_character(tile_img, tile, context_img.as_ref(), &global_colors)
+            };
+            characters.push(character);
+        }
+
+        let optimization = self.options.optimization_time.map(|duration| {
+            self.optimize_characters(&mut characters, &tiles, input_img, &global_colors, duration)
+        });
+
+        if let Some(blink_mask) = blink_mask {
+            self.apply_blink_mask(&mut characters, blink_mask)?;
+        }
+
+        Ok(Conversion {
+            characters,
+            global_colors,
+            optimization,
+        })
+    }
+
+    /// Determine the offset of the grid of cells with the lowest total error
+    ///
+    /// The error is estimated using the per pixel color distances (like when optimizing the global
+    /// colors) for every offset at which the screen still fits into the image. The global colors
+    /// are only determined once at offset (0, 0), since a shift of a few pixels hardly changes
+    /// them; they are determined again when converting at the chosen offset.
+    fn find_grid_offset(&self, input_img: &DynamicImage) -> Result<(u32, u32), String> {
+        if input_img.width() < self.screen_width || input_img.height() < self.screen_height {
+            return Err(format!(
+                "Input file must have a dimension of at least {}x{} pixels.",
+                self.screen_width, self.screen_height
+            ));
+        }
+
+        let max_x = (input_img.width() - self.screen_width)
+            .min(self.profile.charset_definition.character_width - 1);
+        let max_y = (input_img.height() - self.screen_height)
+            .min(self.profile.charset_definition.character_height - 1);
+        let get_tiles = |img: &DynamicImage| -> Vec<TileDiffs> {
+            (0..self.lines)
+                .flat_map(|row| (0..self.columns).map(move |column| (row, column)))
+                .map(|(row, column)| self.get_tile_diffs(&self.get_tile(img, row, column)))
+                .collect()
+        };
+        let img = input_img.crop_imm(0, 0, self.screen_width, self.screen_height);
+        let tiles = get_tiles(&img);
+        let global_colors = self.determine_global_colors(&tiles.iter().collect::<Vec<_>>(), &img);
+
+        let mut best_offset = (0, 0);
+        let mut best_error = u64::MAX;
+        for y in 0..=max_y {
+            for x in 0..=max_x {
+                let img = input_img.crop_imm(x, y, self.screen_width, self.screen_height);
+                let tiles = get_tiles(&img);
+                let error = self.get_total_error(&tiles.iter().collect::<Vec<_>>(), &global_colors);
+                if error < best_error {
+                    best_offset = (x, y);
+                    best_error = error;
+                }
+            }
+        }
+
+        Ok(best_offset)
+    }
+
+    fn get_tile(&self, img: &DynamicImage, row: u32, column: u32) -> RgbImage {
+        img.crop_imm(
+            column * self.profile.charset_definition.character_width,
+            row * self.profile.charset_definition.character_height,
+            self.profile.charset_definition.character_width,
+            self.profile.charset_definition.character_height,
+        )
+        .into_rgb8()
+    }
+
+    /// Determine the code and the overlay of each cell that is covered by a text overlay
+    fn get_overlay_codes(&self) -> Result<Vec<Option<(u8, &TextOverlay)>>, String> {
+        let mut overlay_codes = vec![None; (self.lines * self.columns) as usize];
+        for overlay in self.options.text_overlays {
+            for (offset, c) in overlay.text.chars().enumerate() {
+                let column = overlay.column + offset as u32;
+                if overlay.row >= self.lines || column >= self.columns {
+                    return Err(format!(
+                        "Text \"{}\" at row {}, column {} exceeds the screen of {} lines and {} columns.",
+                        overlay.text,
+                        overlay.row,
+                        overlay.column,
+                        self.lines,
+                        self.columns
+                    ));
+                }
+                let code = (self.profile.screen_code_mapping)(c)
+                    .filter(|&code| {
+                        (code as usize) < self.profile.color_mode.usable_codes()
+                            && !self.excluded_codes[code as usize]
+                    })
+                    .ok_or(format!(
+                        "Character '{}' can't be displayed by profile \"{}\".",
+                        c, self.profile.identifier
+                    ))?;
+                overlay_codes[(overlay.row * self.columns + column) as usize] =
+                    Some((code, overlay));
+            }
+        }
+
+        Ok(overlay_codes)
+    }
+
+    /// Code of the character that fills transparent cells (checked to be displayable)
+    fn get_fill_code(&self) -> Result<Option<u8>, String> {
+        match self.options.transparency.and_then(|t| t.fill_code) {
+            Some(code) if code as usize >= self.profile.color_mode.usable_codes() => Err(format!(
+                "Fill code {} is not available on profile \"{}\".",
+                code, self.profile.identifier
+            )),
+            Some(code) if self.excluded_codes[code as usize] => {
+                Err(format!("Fill code {} is excluded.", code))
+            }
+            fill_code => Ok(fill_code),
+        }
+    }
+
+    /// Create the character of a cell that is not matched (text overlays and transparent cells)
+    ///
+    /// Colors that are not set default to the darkest background color and the foreground color
+    /// with the largest difference in luminance to the background.
+    fn get_fixed_character(
+        &self,
+        code: u8,
+        foreground: Option<u8>,
+        background: Option<u8>,
+        global_colors: &[u8],
+    ) -> Result<Character, String> {
+        let palette = self.profile.palette;
+        let luminance = |color: u8| {
+            let [r, g, b] = palette[color as usize].0;
+            299 * r as i32 + 587 * g as i32 + 114 * b as i32
+        };
+        let background = match self.profile.color_mode {
+            ColorMode::Fixed { background, .. } => background,
+            ColorMode::ForegroundBackground => match background {
+                Some(background) if (background as usize) < palette.len() => background,
+                Some(background) => {
+                    return Err(format!(
+                        "Text background color {} is not available on profile \"{}\".",
+                        background, self.profile.identifier
+                    ))
+                }
+                None => (0..palette.len() as u8)
+                    .min_by_key(|&color| luminance(color))
+                    .expect("No background colors available"),
             },
+            _ => global_colors[0],
+        };
+        let foregrounds: Vec<u8> = match self.profile.color_mode {
+            ColorMode::Fixed { foreground, .. } => vec![foreground],
+            ColorMode::Multicolor { cell_colors } => (0..cell_colors).collect(),
+            _ => (0..palette.len() as u8).collect(),
+        };
+        let foreground = match foreground {
+            Some(foreground) if foregrounds.contains(&foreground) => foreground,
+            Some(foreground) => {
+                return Err(format!(
+                    "Text color {} is not available on profile \"{}\".",
+                    foreground, self.profile.identifier
+                ))
+            }
+            None => foregrounds
+                .iter()
+                .copied()
+                .max_by_key(|&color| luminance(color).abs_diff(luminance(background)))
+                .expect("No foreground colors available"),
+        };
+
+        Ok(Character {
+            code,
+            foreground,
+            background,
+            ..Default::default()
+        })
+    }
+
+    /// Get a tile together with a border of one pixel of the neighboring cells
+    ///
+    /// At the edges of the screen the outermost pixels of the tile are repeated.
+    fn get_context_tile(&self, img: &DynamicImage, row: u32, column: u32) -> RgbImage {
+        let width = self.profile.charset_definition.character_width;
+        let height = self.profile.charset_definition.character_height;
+        let left = (column * width) as i64 - 1;
+        let top = (row * height) as i64 - 1;
+        RgbImage::from_fn(width + 2, height + 2, |x, y| {
+            let x = (left + x as i64).clamp(0, self.screen_width as i64 - 1) as u32;
+            let y = (top + y as i64).clamp(0, self.screen_height as i64 - 1) as u32;
+            let [r, g, b, _] = img.get_pixel(x, y).0;
+            Rgb([r, g, b])
         })
     }
 
-    fn get_best_matching_character(&self, tile: &RgbImage) -> Character {
-        let mut best_character = Character {
-            charset: 0,
-            code: 0,
+    /// Let all cells blink whose area is mostly bright in the blink mask
+    fn apply_blink_mask(
+        &self,
+        characters: &mut [Character],
+        blink_mask: &DynamicImage,
+    ) -> Result<(), String> {
+        if !self.profile.supports_blinking {
+            return Err(format!(
+                "Profile \"{}\" does not support blinking.",
+                self.profile.identifier
+            ));
+        }
+        if blink_mask.width() < self.screen_width || blink_mask.height() < self.screen_height {
+            return Err(format!(
+                "Blink mask must have a dimension of at least {}x{} pixels.",
+                self.screen_width, self.screen_height
+            ));
+        }
+
+        for (i, character) in characters.iter_mut().enumerate() {
+            let row = i as u32 / self.columns;
+            let column = i as u32 - row * self.columns;
+            let tile = self.get_tile(blink_mask, row, column);
+            let set_pixels = tile.pixels().filter(|pixel| is_pixel_set(pixel)).count();
+            character.blink = set_pixels > tile.pixels().len() / 2;
+        }
+
+        Ok(())
+    }
+
+    fn get_tile_diffs(&self, tile: &RgbImage) -> TileDiffs {
+        let color_diffs = get_color_diffs(tile, self.profile.palette, self.options.color_distance);
+        let all_colors: Vec<u8> = (0..self.profile.palette.len() as u8).collect();
+        let foregrounds = match self.profile.color_mode {
+            ColorMode::Fixed { foreground, .. } => vec![foreground],
+            ColorMode::Foreground
+            | ColorMode::ForegroundBackground
+            | ColorMode::ExtendedBackground { .. } => all_colors,
+            ColorMode::Multicolor { cell_colors } => all_colors[..cell_colors as usize].to_vec(),
+        };
+        let is_multicolor = matches!(self.profile.color_mode, ColorMode::Multicolor { .. });
+
+        TileDiffs {
+            hires: self
+                .charsets
+                .iter()
+                .map(|glyphs| {
+                    glyphs
+                        .iter()
+                        .take(self.profile.color_mode.usable_codes())
+                        .map(|glyph| HiresGlyphDiffs::new(glyph, &color_diffs, &foregrounds))
+                        .collect()
+                })
+                .collect(),
+            multicolor: if is_multicolor {
+                self.charsets
+                    .iter()
+                    .map(|glyphs| {
+                        glyphs
+                            .iter()
+                            .map(|glyph| {
+                                MulticolorGlyphDiffs::new(glyph, &color_diffs, &foregrounds)
+                            })
+                            .collect()
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Determine the colors that are shared by all cells (background color first)
+    fn determine_global_colors(&self, tiles: &[&TileDiffs], input_img: &DynamicImage) -> Vec<u8> {
+        match self.profile.color_mode {
+            ColorMode::Fixed { background, .. } => vec![background],
+            ColorMode::ForegroundBackground => Vec::new(),
+            ColorMode::Foreground
+            | ColorMode::Multicolor { .. }
+            | ColorMode::ExtendedBackground { .. } => self.optimize_global_colors(
+                tiles,
+                self.get_most_frequent_colors(input_img, self.profile.global_color_registers.len()),
+            ),
+        }
+    }
+
+    /// Determine the palette color that is closest to the pixels at the edges of the screen
+    fn determine_border_color(&self, input_img: &DynamicImage) -> u8 {
+        let img = input_img
+            .crop_imm(0, 0, self.screen_width, self.screen_height)
+            .into_rgb8();
+        let mut diffs = vec![0; self.profile.palette.len()];
+        for (x, y, pixel) in img.enumerate_pixels() {
+            if x == 0 || y == 0 || x == img.width() - 1 || y == img.height() - 1 {
+                for (diff, color) in diffs.iter_mut().zip(self.profile.palette) {
+                    *diff += self.options.color_distance.diff(pixel, color);
+                }
+            }
+        }
+
+        min_index(&diffs).0
+    }
+
+    /// Determine the palette colors that are closest to most pixels of the image
+    fn get_most_frequent_colors(&self, input_img: &DynamicImage, count: usize) -> Vec<u8> {
+        let mut frequencies = vec![0; self.profile.palette.len()];
+        let img = input_img
+            .crop_imm(0, 0, self.screen_width, self.screen_height)
+            .into_rgb8();
+        for diffs in get_color_diffs(&img, self.profile.palette, self.options.color_distance) {
+            frequencies[min_index(&diffs).0 as usize] += 1;
+        }
+
+        let mut colors: Vec<u8> = (0..frequencies.len() as u8).collect();
+        colors.sort_by_key(|color| std::cmp::Reverse(frequencies[*color as usize]));
+        colors.truncate(count);
+
+        colors
+    }
+
+    /// Improve the global colors by replacing one at a time as long as the total error decreases
+    ///
+    /// Every palette color is tried for each of the global colors, so a single global color (like
+    /// the background color in `Foreground` mode) is guaranteed to be the best one.
+    fn optimize_global_colors(&self, tiles: &[&TileDiffs], initial_colors: Vec<u8>) -> Vec<u8> {
+        let mut best_colors = initial_colors;
+        let mut best_error = self.get_total_error(tiles, &best_colors);
+        let mut improved = true;
+        while improved {
+            improved = false;
+            for i in 0..best_colors.len() {
+                for color in 0..self.profile.palette.len() as u8 {
+                    let mut colors = best_colors.clone();
+                    colors[i] = color;
+                    let error = self.get_total_error(tiles, &colors);
+                    if error < best_error {
+                        best_colors = colors;
+                        best_error = error;
+                        improved = true;
+                    }
+                }
+            }
+        }
+
+        best_colors
+    }
+
+    fn get_total_error(&self, tiles: &[&TileDiffs], global_colors: &[u8]) -> u64 {
+        tiles
+            .iter()
+            .map(|tile| self.get_best_matching_character(tile, global_colors).1 as u64)
+            .sum()
+    }
+
+    /// Determine the best matching character for a tile using the configured matching mode
+    fn determine_character(
+        &self,
+        tile_img: &RgbImage,
+        tile: &TileDiffs,
+        context_img: Option<&RgbImage>,
+        global_colors: &[u8],
+    ) -> Character {
+        if let (Some(sigma), Some(context_img)) = (self.options.low_pass_sigma, context_img) {
+            self.get_best_matching_character_low_pass(context_img, tile, global_colors, sigma)
+        } else if let Some(metric) = self.options.tile_metric {
+            self.get_best_matching_character_by_metric(tile_img, tile, global_colors, metric)
+        } else {
+            self.get_best_matching_character(tile, global_colors).0
+        }
+    }
+
+    /// Distribute the color error of a cell to the cells that haven't been matched yet
+    ///
+    /// Uses the weights of the Floyd-Steinberg dithering, just at cell instead of pixel
+    /// granularity.
+    fn diffuse_error(&self, errors: &mut [[f32; 3]], row: u32, column: u32, error: [f32; 3]) {
+        let neighbors: [(i64, i64, f32); 4] = [
+            (0, 1, 7.0 / 16.0),
+            (1, -1, 3.0 / 16.0),
+            (1, 0, 5.0 / 16.0),
+            (1, 1, 1.0 / 16.0),
+        ];
+        for (row_offset, column_offset, weight) in neighbors {
+            let neighbor_row = row as i64 + row_offset;
+            let neighbor_column = column as i64 + column_offset;
+            if neighbor_row >= self.lines as i64
+                || neighbor_column < 0
+                || neighbor_column >= self.columns as i64
+            {
+                continue;
+            }
+            let index = (neighbor_row * self.columns as i64 + neighbor_column) as usize;
+            for (channel, value) in errors[index].iter_mut().zip(error) {
+                *channel += value * weight;
+            }
+        }
+    }
+
+    /// Determine the best matching character for a tile and its difference to the tile
+    fn get_best_matching_character(
+        &self,
+        tile: &TileDiffs,
+        global_colors: &[u8],
+    ) -> (Character, u32) {
+        self.get_candidates(tile, global_colors)
+            .into_iter()
+            .min_by_key(|(_, diff)| *diff)
+            .expect("No candidate characters available")
+    }
+
+    /// Determine the best matching character for a tile by comparing the rendered candidates
+    /// with the tile using the passed metric
+    fn get_best_matching_character_by_metric(
+        &self,
+        tile_img: &RgbImage,
+        tile: &TileDiffs,
+        global_colors: &[u8],
+        metric: &dyn TileMetric,
+    ) -> Character {
+        self.get_candidates(tile, global_colors)
+            .into_iter()
+            .map(|(character, _)| {
+                let diff = metric.diff(tile_img, &self.render_character(&character, global_colors));
+                (character, diff)
+            })
+            .min_by_key(|(_, diff)| *diff)
+            .expect("No candidate characters available")
+            .0
+    }
+
+    /// Determine the best matching character for a tile by comparing gaussian blurred versions of
+    /// the tile and the rendered candidates
+    ///
+    /// This compares the perceived shading instead of exact pixel positions, so dithered glyphs
+    /// get chosen for mid-tones. The candidates are rendered into the context tile (which contains
+    /// a border of one pixel of the neighboring cells), so the blur also takes the transition to the
+    /// neighboring cells into account.
+    fn get_best_matching_character_low_pass(
+        &self,
+        context_img: &RgbImage,
+        tile: &TileDiffs,
+        global_colors: &[u8],
+        sigma: f32,
+    ) -> Character {
+        let color_distance = self.options.color_distance;
+        let blurred_context_img = imageops::blur(context_img, sigma);
+        self.get_candidates(tile, global_colors)
+            .into_iter()
+            .map(|(character, _)| {
+                let mut candidate_img = context_img.clone();
+                candidate_img
+                    .copy_from(&self.render_character(&character, global_colors), 1, 1)
+                    .expect("Unable to put character into context tile");
+                let diff = image_diff(
+                    &blurred_context_img,
+                    &imageops::blur(&candidate_img, sigma),
+                    |a, b| color_distance.diff(a, b),
+                );
+                (character, diff)
+            })
+            .min_by_key(|(_, diff)| *diff)
+            .expect("No candidate characters available")
+            .0
+    }
+
+    /// Determine each glyph in its best matching colors together with its difference to the tile
+    ///
+    /// The colors are determined by the per pixel color distances. Hires candidates come first,
+    /// followed by the multicolor candidates (if multicolor mode is used). Excluded codes are
+    /// skipped.
+    fn get_candidates(&self, tile: &TileDiffs, global_colors: &[u8]) -> Vec<(Character, u32)> {
+        let all_colors: Vec<u8> = (0..self.profile.palette.len() as u8).collect();
+        let backgrounds = match self.profile.color_mode {
+            ColorMode::ForegroundBackground => &all_colors,
+            ColorMode::ExtendedBackground { .. } => global_colors,
+            _ => &global_colors[..1],
         };
-        let mut best_diff = u32::MAX;
-        for (charset, characters) in self.charsets.iter().enumerate() {
-            for (code, character) in characters.iter().enumerate() {
-                let diff = image_diff(&tile, character);
-                if diff < best_diff {
-                    best_character = Character {
+        let is_extended_background = matches!(
+            self.profile.color_mode,
+            ColorMode::ExtendedBackground { .. }
+        );
+
+        let mut candidates = Vec::new();
+        for (charset, glyphs) in tile.hires.iter().enumerate() {
+            for (code, glyph) in glyphs.iter().enumerate() {
+                if self.excluded_codes[code] {
+                    continue;
+                }
+                let (background, background_diff) =
+                    min_candidate(&glyph.background_diffs, backgrounds);
+                let background_index = if is_extended_background {
+                    backgrounds
+                        .iter()
+                        .position(|&color| color == background)
+                        .expect("Background color is not a global color") as u8
+                } else {
+                    0
+                };
+                candidates.push((
+                    Character {
                         charset: charset as u32,
                         code: code as u8,
-                    };
-                    best_diff = diff;
+                        foreground: glyph.foreground,
+                        background,
+                        background_index,
+                        ..Default::default()
+                    },
+                    glyph.foreground_diff + background_diff,
+                ));
+            }
+        }
+        for (charset, glyphs) in tile.multicolor.iter().enumerate() {
+            for (code, glyph) in glyphs.iter().enumerate() {
+                if self.excluded_codes[code] {
+                    continue;
                 }
+                let diff = glyph.foreground_diff
+                    + glyph
+                        .global_color_diffs
+                        .iter()
+                        .zip(global_colors)
+                        .map(|(diffs, &color)| diffs[color as usize])
+                        .sum::<u32>();
+                candidates.push((
+                    Character {
+                        charset: charset as u32,
+                        code: code as u8,
+                        foreground: glyph.foreground,
+                        background: global_colors[0],
+                        multicolor: true,
+                        ..Default::default()
+                    },
+                    diff,
+                ));
             }
         }
 
-        best_character
+        candidates
     }
 
-    fn create_preview(&self, characters: &Vec<Character>) -> RgbImage {
+    fn render_character(&self, character: &Character, global_colors: &[u8]) -> RgbImage {
+        let palette = self.profile.palette;
+        let glyph = &self.charsets[character.charset as usize][character.code as usize];
+        if character.multicolor {
+            glyph.render_multicolor([
+                palette[character.background as usize],
+                palette[global_colors[1] as usize],
+                palette[global_colors[2] as usize],
+                palette[character.foreground as usize],
+            ])
+        } else {
+            glyph.render(
+                palette[character.foreground as usize],
+                palette[character.background as usize],
+            )
+        }
+    }
+
+    fn create_preview(&self, characters: &[Character], global_colors: &[u8]) -> RgbImage {
         let mut preview_img = RgbImage::new(self.screen_width, self.screen_height);
         for (i, character) in characters.iter().enumerate() {
-            let row = i as u32 / self.profile.columns;
-            let column = i as u32 - row * self.profile.columns;
+            let row = i as u32 / self.columns;
+            let column = i as u32 - row * self.columns;
 
             preview_img
                 .copy_from(
-                    &self.charsets[character.charset as usize][character.code as usize],
+                    &self.render_character(character, global_colors),
                     column * self.profile.charset_definition.character_width,
                     row * self.profile.charset_definition.character_height,
                 )
@@ -156,17 +1228,192 @@ impl<'a> Converter<'a> {
         preview_img
     }
 
-    fn map_character_ram(&self, characters: &Vec<Character>) -> Vec<u8> {
-        characters
-            .into_iter()
+    fn map_character_ram(
+        &self,
+        characters: &[Character],
+        global_colors: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        Ok(self
+            .place_window(characters, global_colors)?
+            .iter()
             .map(self.profile.character_ram_mapping)
-            .collect()
+            .collect())
+    }
+
+    fn map_color_ram(
+        &self,
+        characters: &[Character],
+        global_colors: &[u8],
+    ) -> Result<Option<Vec<u8>>, String> {
+        self.profile
+            .color_ram_mapping
+            .map(|mapping| {
+                Ok(self
+                    .place_window(characters, global_colors)?
+                    .iter()
+                    .map(mapping)
+                    .collect())
+            })
+            .transpose()
     }
 
-    fn map_color_ram(&self, characters: &Vec<Character>) -> Option<Vec<u8>> {
-        match self.profile.color_ram_mapping {
-            Some(mapping) => Some(characters.iter().map(mapping).collect()),
-            None => None,
+    /// Put the characters of the window into the cells of the whole screen (unless only the
+    /// window is output)
+    ///
+    /// The cells outside of the window are filled with spaces (or another blank glyph if the space
+    /// is excluded) in the default colors of text overlays, so text that is written there later is
+    /// readable.
+    fn place_window(
+        &self,
+        characters: &[Character],
+        global_colors: &[u8],
+    ) -> Result<Vec<Character>, String> {
+        if self.options.compact_window {
+            return Ok(characters.to_vec());
         }
+
+        let space = self.get_fixed_character(self.get_blank_code()?, None, None, global_colors)?;
+        let mut screen = vec![space; (self.profile.lines * self.profile.columns) as usize];
+        for (i, character) in characters.iter().enumerate() {
+            let row = self.window.row + i as u32 / self.columns;
+            let column = self.window.column + i as u32 % self.columns;
+            screen[(row * self.profile.columns + column) as usize] = character.clone();
+        }
+
+        Ok(screen)
+    }
+
+    /// Code of a blank glyph that is not excluded, preferably the space
+    fn get_blank_code(&self) -> Result<u8, String> {
+        let is_available = |code: u8| {
+            (code as usize) < self.profile.color_mode.usable_codes()
+                && !self.excluded_codes[code as usize]
+        };
+        (self.profile.screen_code_mapping)(' ')
+            .filter(|&code| is_available(code))
+            .or_else(|| {
+                (0..self.profile.color_mode.usable_codes())
+                    .map(|code| code as u8)
+                    .filter(|&code| is_available(code))
+                    .find(|&code| !self.charsets[0][code as usize].pixels().any(|set| set))
+            })
+            .ok_or(format!(
+                "Profile \"{}\" has no blank character left to fill the screen outside of the window.",
+                self.profile.identifier
+            ))
+    }
+
+    fn map_registers(&self, global_colors: &[u8], input_img: &DynamicImage) -> Vec<Register> {
+        let mut registers = Vec::new();
+        if let Some(address) = self.profile.border_color_register {
+            registers.push(Register {
+                address,
+                value: self.determine_border_color(input_img),
+            });
+        }
+        registers.extend(
+            self.profile
+                .global_color_registers
+                .iter()
+                .zip(global_colors)
+                .map(|(&address, &value)| Register { address, value }),
+        );
+
+        registers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use profiles::{C64_EXTENDED_BACKGROUND_PROFILE, C64_MULTICOLOR_PROFILE, C64_PROFILE};
+
+    #[test]
+    fn hires_and_multicolor_cells_are_told_apart() {
+        let options = ConversionOptions::default();
+        let converter = Converter::new(&C64_MULTICOLOR_PROFILE, None, &[], &options);
+        let palette = C64_MULTICOLOR_PROFILE.palette;
+        let global_colors = [0x00, 0x01, 0x02];
+        let glyph = &converter.charsets[0][0x01];
+
+        let hires_tile = glyph.render(palette[0x05], palette[0x00]);
+        let (character, diff) = converter
+            .get_best_matching_character(&converter.get_tile_diffs(&hires_tile), &global_colors);
+        assert_eq!(diff, 0);
+        assert!(!character.multicolor);
+        assert_eq!((character.code, character.foreground), (0x01, 0x05));
+
+        let multicolor_tile =
+            glyph.render_multicolor([palette[0x00], palette[0x01], palette[0x02], palette[0x05]]);
+        let (character, diff) = converter.get_best_matching_character(
+            &converter.get_tile_diffs(&multicolor_tile),
+            &global_colors,
+        );
+        assert_eq!(diff, 0);
+        assert!(character.multicolor);
+        assert_eq!(character.foreground, 0x05);
+    }
+
+    #[test]
+    fn extended_background_candidates_select_the_background_register() {
+        let options = ConversionOptions::default();
+        let converter = Converter::new(&C64_EXTENDED_BACKGROUND_PROFILE, None, &[], &options);
+        let palette = C64_EXTENDED_BACKGROUND_PROFILE.palette;
+        let global_colors = [0x00, 0x02, 0x05, 0x06];
+        let tile = converter.charsets[0][0x01].render(palette[0x01], palette[0x05]);
+
+        let candidates = converter.get_candidates(&converter.get_tile_diffs(&tile), &global_colors);
+        let (character, diff) = candidates
+            .iter()
+            .find(|(character, _)| character.code == 0x01)
+            .expect("Character is not a candidate");
+        assert_eq!(*diff, 0);
+        assert_eq!(
+            (character.background, character.background_index),
+            (0x05, 2)
+        );
+    }
+
+    #[test]
+    fn windows_must_fit_into_the_screen() {
+        let window = |row, column, lines, columns| Window {
+            row,
+            column,
+            lines,
+            columns,
+        };
+        assert!(window(0, 0, 25, 40).check(&C64_PROFILE).is_ok());
+        assert!(window(20, 30, 5, 10).check(&C64_PROFILE).is_ok());
+        assert!(window(20, 30, 6, 10).check(&C64_PROFILE).is_err());
+        assert!(window(0, 0, 0, 40).check(&C64_PROFILE).is_err());
+        assert!(window(u32::MAX, 0, 1, 40).check(&C64_PROFILE).is_err());
+        assert!(window(0, 1, 25, u32::MAX).check(&C64_PROFILE).is_err());
+    }
+
+    #[test]
+    fn crop_and_offset_must_fit_into_the_image() {
+        let img = DynamicImage::new_rgb8(320, 200);
+        let crop = |x, y, width, height| Crop {
+            x,
+            y,
+            width,
+            height,
+        };
+        let cropped = crop(10, 20, 100, 50).apply(&img).unwrap();
+        assert_eq!(cropped.dimensions(), (100, 50));
+        assert!(crop(300, 0, 21, 200).apply(&img).is_err());
+        assert!(crop(0, 0, 320, 0).apply(&img).is_err());
+        assert!(crop(1, 0, u32::MAX, 200).apply(&img).is_err());
+
+        assert_eq!(
+            apply_offset(&img, (0, 0), 320, 200).unwrap().dimensions(),
+            (320, 200)
+        );
+        assert_eq!(
+            apply_offset(&img, (8, 4), 312, 196).unwrap().dimensions(),
+            (312, 196)
+        );
+        assert!(apply_offset(&img, (8, 4), 320, 196).is_err());
+        assert!(apply_offset(&img, (u32::MAX, 0), 320, 200).is_err());
     }
 }
@@ -0,0 +1,208 @@
+use image::{Rgb, RgbImage};
+
+use crate::{color::ColorDistance, glyph::Glyph};
+
+/// Differences of each pixel of a tile to each color of the palette
+pub type ColorDiffs = Vec<Vec<u32>>;
+
+pub fn get_color_diffs(
+    tile: &RgbImage,
+    palette: &[Rgb<u8>],
+    color_distance: ColorDistance,
+) -> ColorDiffs {
+    tile.pixels()
+        .map(|pixel| {
+            palette
+                .iter()
+                .map(|color| color_distance.diff(pixel, color))
+                .collect()
+        })
+        .collect()
+}
+
+/// Differences between a tile and a glyph displayed in hires mode
+///
+/// Since every pixel is either drawn in the foreground or the background color, the difference
+/// can be summed up from the per pixel differences to each palette color instead of rendering the
+/// glyph in every color combination. The best foreground color doesn't depend on the background
+/// color and can therefore be chosen in advance.
+pub struct HiresGlyphDiffs {
+    pub foreground: u8,
+    pub foreground_diff: u32,
+    /// Difference of the unset pixels for each palette color
+    pub background_diffs: Vec<u32>,
+}
+
+impl HiresGlyphDiffs {
+    pub fn new(glyph: &Glyph, tile: &ColorDiffs, foregrounds: &[u8]) -> Self {
+        let palette_size = tile[0].len();
+        let mut foreground_diffs = vec![0; palette_size];
+        let mut background_diffs = vec![0; palette_size];
+        for (is_set, diffs) in glyph.pixels().zip(tile) {
+            let sums = if is_set {
+                &mut foreground_diffs
+            } else {
+                &mut background_diffs
+            };
+            for (sum, diff) in sums.iter_mut().zip(diffs) {
+                *sum += diff;
+            }
+        }
+        let (foreground, foreground_diff) = min_candidate(&foreground_diffs, foregrounds);
+
+        Self {
+            foreground,
+            foreground_diff,
+            background_diffs,
+        }
+    }
+}
+
+/// Differences between a tile and a glyph displayed in multicolor mode
+///
+/// Each pair of horizontally adjacent glyph pixels selects one of the three global colors (bit
+/// pairs 00, 01 and 10) or the cell color (bit pair 11).
+pub struct MulticolorGlyphDiffs {
+    pub foreground: u8,
+    pub foreground_diff: u32,
+    /// Difference of the pixels of the bit pairs 00, 01 and 10 for each palette color
+    pub global_color_diffs: [Vec<u32>; 3],
+}
+
+impl MulticolorGlyphDiffs {
+    pub fn new(glyph: &Glyph, tile: &ColorDiffs, cell_colors: &[u8]) -> Self {
+        let palette_size = tile[0].len();
+        let mut sums = [
+            vec![0; palette_size],
+            vec![0; palette_size],
+            vec![0; palette_size],
+            vec![0; palette_size],
+        ];
+        for y in 0..glyph.height() {
+            for x in (0..glyph.width()).step_by(2) {
+                let value = (glyph.is_set(x, y) as usize) << 1 | glyph.is_set(x + 1, y) as usize;
+                let left = &tile[(y * glyph.width() + x) as usize];
+                let right = &tile[(y * glyph.width() + x + 1) as usize];
+                for (color, sum) in sums[value].iter_mut().enumerate() {
+                    *sum += left[color] + right[color];
+                }
+            }
+        }
+        let [background_diffs, color_1_diffs, color_2_diffs, foreground_diffs] = sums;
+        let (foreground, foreground_diff) = min_candidate(&foreground_diffs, cell_colors);
+
+        Self {
+            foreground,
+            foreground_diff,
+            global_color_diffs: [background_diffs, color_1_diffs, color_2_diffs],
+        }
+    }
+}
+
+/// Differences between a tile and all glyphs of all charsets
+pub struct TileDiffs {
+    pub hires: Vec<Vec<HiresGlyphDiffs>>,
+    /// Only determined if multicolor mode is used
+    pub multicolor: Vec<Vec<MulticolorGlyphDiffs>>,
+}
+
+/// Index and value of the smallest element
+pub fn min_index(values: &[u32]) -> (u8, u32) {
+    let (index, value) = values
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, value)| **value)
+        .expect("Unable to determine minimum of empty list");
+
+    (index as u8, *value)
+}
+
+/// Candidate index with the smallest value
+pub fn min_candidate(values: &[u32], candidates: &[u8]) -> (u8, u32) {
+    candidates
+        .iter()
+        .map(|&candidate| (candidate, values[candidate as usize]))
+        .min_by_key(|(_, value)| *value)
+        .expect("Unable to determine minimum of empty candidate list")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PALETTE: [Rgb<u8>; 4] = [
+        Rgb([0x00, 0x00, 0x00]),
+        Rgb([0xff, 0xff, 0xff]),
+        Rgb([0x88, 0x39, 0x32]),
+        Rgb([0x67, 0xb6, 0xbd]),
+    ];
+
+    fn glyph() -> Glyph {
+        Glyph::from_image(&RgbImage::from_fn(8, 8, |x, y| {
+            if (x * 3 + y * 5) % 7 < 3 {
+                Rgb([0xff, 0xff, 0xff])
+            } else {
+                Rgb([0x00, 0x00, 0x00])
+            }
+        }))
+    }
+
+    fn tile() -> RgbImage {
+        RgbImage::from_fn(8, 8, |x, y| {
+            Rgb([
+                (x * 37 + y * 11) as u8,
+                (x * y * 7) as u8,
+                (200 - x * 13 - y * 5) as u8,
+            ])
+        })
+    }
+
+    fn distance(tile: &RgbImage, rendered: &RgbImage) -> u32 {
+        tile.pixels()
+            .zip(rendered.pixels())
+            .map(|(a, b)| ColorDistance::Rgb.diff(a, b))
+            .sum()
+    }
+
+    #[test]
+    fn hires_diffs_match_rendered_glyph() {
+        let (glyph, tile) = (glyph(), tile());
+        let diffs = HiresGlyphDiffs::new(
+            &glyph,
+            &get_color_diffs(&tile, &PALETTE, ColorDistance::Rgb),
+            &[0, 1, 2, 3],
+        );
+        for (&background, background_diff) in PALETTE.iter().zip(&diffs.background_diffs) {
+            let diff = diffs.foreground_diff + background_diff;
+            let rendered = glyph.render(PALETTE[diffs.foreground as usize], background);
+            assert_eq!(diff, distance(&tile, &rendered));
+            // the foreground color chosen in advance is the best one for every background color
+            for foreground in PALETTE {
+                assert!(diff <= distance(&tile, &glyph.render(foreground, background)));
+            }
+        }
+    }
+
+    #[test]
+    fn multicolor_diffs_match_rendered_glyph() {
+        let (glyph, tile) = (glyph(), tile());
+        let diffs = MulticolorGlyphDiffs::new(
+            &glyph,
+            &get_color_diffs(&tile, &PALETTE, ColorDistance::Rgb),
+            &[0, 1, 2],
+        );
+        for global_colors in [[0, 1, 2], [3, 2, 1], [1, 1, 3]] {
+            let diff = diffs.foreground_diff
+                + (0..3)
+                    .map(|i| diffs.global_color_diffs[i][global_colors[i]])
+                    .sum::<u32>();
+            let rendered = glyph.render_multicolor([
+                PALETTE[global_colors[0]],
+                PALETTE[global_colors[1]],
+                PALETTE[global_colors[2]],
+                PALETTE[diffs.foreground as usize],
+            ]);
+            assert_eq!(diff, distance(&tile, &rendered));
+        }
+    }
+}
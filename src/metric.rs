@@ -0,0 +1,154 @@
+use std::str::FromStr;
+
+use image::{imageops, RgbImage};
+
+use crate::{
+    color::ColorDistance,
+    utils::{image_diff, parse_identifier},
+};
+
+/// Metric for the difference between a tile of the input image and a rendered character
+///
+/// Can be implemented by library users to experiment with their own metrics.
+pub trait TileMetric {
+    /// Difference between two images of the same dimension
+    ///
+    /// 0 means the images are equal
+    fn diff(&self, a: &RgbImage, b: &RgbImage) -> u32;
+}
+
+/// Built-in tile metric (for selecting it by an identifier)
+#[derive(Clone, Copy, Debug)]
+pub enum TileMetricKind {
+    MeanAbsoluteError,
+    SquaredError,
+    BlurredError,
+    StructuralError,
+}
+
+const TILE_METRIC_IDENTIFIERS: &[(&str, TileMetricKind)] = &[
+    ("mae", TileMetricKind::MeanAbsoluteError),
+    ("squared", TileMetricKind::SquaredError),
+    ("blurred", TileMetricKind::BlurredError),
+    ("ssim", TileMetricKind::StructuralError),
+];
+
+impl FromStr for TileMetricKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_identifier(s, TILE_METRIC_IDENTIFIERS, "tile metric")
+    }
+}
+
+impl TileMetricKind {
+    /// Create the metric (`sigma` is the standard deviation of the blur of `BlurredError`)
+    pub fn create(
+        &self,
+        color_distance: ColorDistance,
+        sigma: f32,
+    ) -> Result<Box<dyn TileMetric>, String> {
+        if sigma <= 0.0 || !sigma.is_finite() {
+            return Err(format!(
+                "Tile metric sigma {} must be a positive number.",
+                sigma
+            ));
+        }
+
+        Ok(match self {
+            TileMetricKind::MeanAbsoluteError => Box::new(MeanAbsoluteError { color_distance }),
+            TileMetricKind::SquaredError => Box::new(SquaredError { color_distance }),
+            TileMetricKind::BlurredError => Box::new(BlurredError {
+                color_distance,
+                sigma,
+            }),
+            TileMetricKind::StructuralError => Box::new(StructuralError),
+        })
+    }
+}
+
+/// Mean of the per pixel color distances
+pub struct MeanAbsoluteError {
+    pub color_distance: ColorDistance,
+}
+
+impl TileMetric for MeanAbsoluteError {
+    fn diff(&self, a: &RgbImage, b: &RgbImage) -> u32 {
+        image_diff(a, b, |pa, pb| self.color_distance.diff(pa, pb))
+    }
+}
+
+/// Mean of the squared per pixel color distances
+///
+/// Penalizes single strongly deviating pixels more than many slightly deviating ones.
+pub struct SquaredError {
+    pub color_distance: ColorDistance,
+}
+
+impl TileMetric for SquaredError {
+    fn diff(&self, a: &RgbImage, b: &RgbImage) -> u32 {
+        image_diff(a, b, |pa, pb| self.color_distance.diff(pa, pb).pow(2))
+    }
+}
+
+/// Mean of the per pixel color distances of low-pass filtered (gaussian blurred) images
+///
+/// Compares the perceived shading rather than the exact pixel positions.
+pub struct BlurredError {
+    pub color_distance: ColorDistance,
+    /// Standard deviation of the gaussian blur
+    pub sigma: f32,
+}
+
+impl TileMetric for BlurredError {
+    fn diff(&self, a: &RgbImage, b: &RgbImage) -> u32 {
+        image_diff(
+            &imageops::blur(a, self.sigma),
+            &imageops::blur(b, self.sigma),
+            |pa, pb| self.color_distance.diff(pa, pb),
+        )
+    }
+}
+
+/// Structural dissimilarity based on the SSIM index of the luminance of both images
+///
+/// Only the luminance is compared, so colors have to be chosen by other means. The result is
+/// scaled to the range 0 to 765 like the color distances.
+///
+/// https://en.wikipedia.org/wiki/Structural_similarity_index_measure
+pub struct StructuralError;
+
+impl TileMetric for StructuralError {
+    fn diff(&self, a: &RgbImage, b: &RgbImage) -> u32 {
+        assert!(a.width() == b.width() && a.height() == b.height());
+        let luminance = |img: &RgbImage| -> Vec<f32> {
+            img.pixels()
+                .map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32)
+                .collect()
+        };
+        let la = luminance(a);
+        let lb = luminance(b);
+        let n = la.len() as f32;
+
+        let mean_a = la.iter().sum::<f32>() / n;
+        let mean_b = lb.iter().sum::<f32>() / n;
+        let mut variance_a = 0.0;
+        let mut variance_b = 0.0;
+        let mut covariance = 0.0;
+        for (pa, pb) in la.iter().zip(&lb) {
+            variance_a += (pa - mean_a).powi(2);
+            variance_b += (pb - mean_b).powi(2);
+            covariance += (pa - mean_a) * (pb - mean_b);
+        }
+        variance_a /= n;
+        variance_b /= n;
+        covariance /= n;
+
+        let c1 = (0.01f32 * 255.0).powi(2);
+        let c2 = (0.03f32 * 255.0).powi(2);
+        let ssim = ((2.0 * mean_a * mean_b + c1) * (2.0 * covariance + c2))
+            / ((mean_a.powi(2) + mean_b.powi(2) + c1) * (variance_a + variance_b + c2));
+
+        ((1.0 - ssim) / 2.0 * 765.0).round() as u32
+    }
+}
@@ -0,0 +1,237 @@
+use std::time::{Duration, Instant};
+
+use image::{imageops, DynamicImage, GenericImage, RgbImage};
+
+use crate::{matching::TileDiffs, profiles::Character, Converter, OptimizationReport};
+
+/// Number of candidates per cell (the ones with the lowest per cell error) that are tried
+const CANDIDATES_PER_CELL: usize = 16;
+
+impl Converter<'_> {
+    /// Improve the characters by simulated annealing on the blurred rendering of the whole screen
+    ///
+    /// In contrast to matching each cell on its own, this takes into account how adjacent
+    /// characters look together. Random cells get assigned one of their best candidates and
+    /// changes that make the whole screen look worse are accepted with a decreasing probability.
+    /// Cells without tile (covered by text overlays) keep their character. The best assignment
+    /// that was found is kept, so the result is never worse than the passed characters.
+    pub(crate) fn optimize_characters(
+        &self,
+        characters: &mut [Character],
+        tiles: &[Option<TileDiffs>],
+        input_img: &DynamicImage,
+        global_colors: &[u8],
+        duration: Duration,
+    ) -> OptimizationReport {
+        let sigma = self.options.low_pass_sigma.unwrap_or(1.0);
+        let margin = (3.0 * sigma).ceil() as u32;
+        let cell_width = self.profile.charset_definition.character_width;
+        let cell_height = self.profile.charset_definition.character_height;
+
+        let target = imageops::blur(
+            &input_img
+                .crop_imm(0, 0, self.screen_width, self.screen_height)
+                .into_rgb8(),
+            sigma,
+        );
+        let mut rendering = self.create_preview(characters, global_colors);
+        let mut blurred = imageops::blur(&rendering, sigma);
+        let initial_error = self.get_region_error(&blurred, &target, 0, 0);
+        let mut error = initial_error;
+        let initial_characters = characters.to_vec();
+        let mut best_characters = initial_characters.clone();
+        let mut best_error = initial_error;
+
+        let candidates: Vec<Vec<Character>> = tiles
+            .iter()
+            .map(|tile| match tile {
+                Some(tile) => {
+                    let mut candidates = self.get_candidates(tile, global_colors);
+                    candidates.sort_by_key(|(_, diff)| *diff);
+                    candidates
+                        .into_iter()
+                        .take(CANDIDATES_PER_CELL)
+                        .map(|(character, _)| character)
+                        .collect()
+                }
+                None => Vec::new(),
+            })
+            .collect();
+        let cells: Vec<usize> = (0..candidates.len())
+            .filter(|&cell| candidates[cell].len() > 1)
+            .collect();
+
+        let mut random = Random(0x2144_5eed);
+        let start = Instant::now();
+        let initial_temperature = 2.0 * (cell_width * cell_height) as f64;
+        while !cells.is_empty() && start.elapsed() < duration {
+            let cell = cells[random.next() as usize % cells.len()];
+            let character = &candidates[cell][random.next() as usize % candidates[cell].len()];
+            let row = cell as u32 / self.columns;
+            let column = cell as u32 % self.columns;
+
+            // region whose blurred pixels are affected by the cell, and the region that is needed
+            // to blur it correctly
+            let affected = Region::around(
+                column * cell_width,
+                row * cell_height,
+                cell_width,
+                cell_height,
+                margin,
+                self.screen_width,
+                self.screen_height,
+            );
+            let context = affected.expand(margin, self.screen_width, self.screen_height);
+
+            let mut patch = imageops::crop_imm(
+                &rendering,
+                context.x,
+                context.y,
+                context.width,
+                context.height,
+            )
+            .to_image();
+            patch
+                .copy_from(
+                    &self.render_character(character, global_colors),
+                    column * cell_width - context.x,
+                    row * cell_height - context.y,
+                )
+                .expect("Unable to put character into patch");
+            let blurred_patch = imageops::crop_imm(
+                &imageops::blur(&patch, sigma),
+                affected.x - context.x,
+                affected.y - context.y,
+                affected.width,
+                affected.height,
+            )
+            .to_image();
+            let current_patch = imageops::crop_imm(
+                &blurred,
+                affected.x,
+                affected.y,
+                affected.width,
+                affected.height,
+            )
+            .to_image();
+
+            let delta = self.get_region_error(&blurred_patch, &target, affected.x, affected.y)
+                as i64
+                - self.get_region_error(&current_patch, &target, affected.x, affected.y) as i64;
+            let progress = start.elapsed().as_secs_f64() / duration.as_secs_f64();
+            let temperature = initial_temperature * (1.0 - progress).max(0.0);
+            let accept = delta < 0
+                || (temperature > 0.0 && random.next_f64() < (-(delta as f64) / temperature).exp());
+            if accept {
+                characters[cell] = character.clone();
+                rendering
+                    .copy_from(
+                        &self.render_character(character, global_colors),
+                        column * cell_width,
+                        row * cell_height,
+                    )
+                    .expect("Unable to put character into rendering");
+                blurred
+                    .copy_from(&blurred_patch, affected.x, affected.y)
+                    .expect("Unable to put patch into blurred rendering");
+                error = (error as i64 + delta) as u64;
+                if error < best_error {
+                    best_error = error;
+                    best_characters.clone_from_slice(characters);
+                }
+            }
+        }
+
+        // the error was updated per patch, so check the result on the whole screen
+        let mut final_error = self.get_region_error(
+            &imageops::blur(&self.create_preview(&best_characters, global_colors), sigma),
+            &target,
+            0,
+            0,
+        );
+        if final_error < initial_error {
+            characters.clone_from_slice(&best_characters);
+        } else {
+            characters.clone_from_slice(&initial_characters);
+            final_error = initial_error;
+        }
+
+        let pixel_count = (self.screen_width * self.screen_height) as f64;
+        OptimizationReport {
+            initial_error: initial_error as f64 / pixel_count,
+            final_error: final_error as f64 / pixel_count,
+        }
+    }
+
+    /// Sum of the color distances between an image and the region of the target at x, y
+    fn get_region_error(&self, img: &RgbImage, target: &RgbImage, x: u32, y: u32) -> u64 {
+        img.enumerate_pixels()
+            .map(|(px, py, pixel)| {
+                self.options
+                    .color_distance
+                    .diff(pixel, target.get_pixel(x + px, y + py)) as u64
+            })
+            .sum()
+    }
+}
+
+/// Rectangular area of the screen
+struct Region {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Region {
+    /// Area of the passed rectangle extended by a margin (limited to the screen)
+    fn around(
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        margin: u32,
+        screen_width: u32,
+        screen_height: u32,
+    ) -> Self {
+        let left = x.saturating_sub(margin);
+        let top = y.saturating_sub(margin);
+        let right = (x + width + margin).min(screen_width);
+        let bottom = (y + height + margin).min(screen_height);
+
+        Self {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        }
+    }
+
+    fn expand(&self, margin: u32, screen_width: u32, screen_height: u32) -> Self {
+        Self::around(
+            self.x,
+            self.y,
+            self.width,
+            self.height,
+            margin,
+            screen_width,
+            screen_height,
+        )
+    }
+}
+
+/// Reproducible pseudo-random numbers (xorshift)
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt;
+
+use image::{imageops, DynamicImage, RgbImage};
+
+/// Adjustments that are applied to the image before it gets converted
+///
+/// The steps are applied in the order of the fields. Unset steps are skipped.
+#[derive(Clone, Debug, Default)]
+pub struct Preprocessing {
+    /// Stretch the brightness range so that the darkest and brightest pixels (ignoring 0.5% of
+    /// outliers on each side) become black and white
+    pub auto_levels: bool,
+    /// Gamma correction (values above 1 brighten the mid-tones)
+    pub gamma: Option<f32>,
+    /// Contrast factor (1 leaves the image unchanged)
+    pub contrast: Option<f32>,
+    /// Saturation factor (0 gives a grayscale image, 1 leaves the image unchanged)
+    pub saturation: Option<f32>,
+    /// Sharpen the image by subtracting a blurred version
+    pub unsharp_mask: Option<UnsharpMask>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct UnsharpMask {
+    /// Standard deviation of the gaussian blur
+    pub sigma: f32,
+    /// Minimal brightness difference for pixels to get sharpened
+    pub threshold: i32,
+}
+
+impl Preprocessing {
+    pub fn apply(&self, img: &DynamicImage) -> Result<DynamicImage, String> {
+        self.check()?;
+
+        let mut img = img.to_rgb8();
+        if self.auto_levels {
+            auto_levels(&mut img);
+        }
+        if let Some(gamma) = self.gamma {
+            map_channels(&mut img, |value| 255.0 * (value / 255.0).powf(1.0 / gamma));
+        }
+        if let Some(contrast) = self.contrast {
+            map_channels(&mut img, |value| (value - 128.0) * contrast + 128.0);
+        }
+        if let Some(saturation) = self.saturation {
+            for pixel in img.pixels_mut() {
+                let [r, g, b] = pixel.0.map(f32::from);
+                let luminance = 0.299 * r + 0.587 * g + 0.114 * b;
+                pixel.0 = [r, g, b].map(|value| {
+                    (luminance + (value - luminance) * saturation)
+                        .round()
+                        .clamp(0.0, 255.0) as u8
+                });
+            }
+        }
+        if let Some(unsharp_mask) = self.unsharp_mask {
+            img = imageops::unsharpen(&img, unsharp_mask.sigma, unsharp_mask.threshold);
+        }
+
+        Ok(DynamicImage::from(img))
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if let Some(gamma) = self
+            .gamma
+            .filter(|gamma| *gamma <= 0.0 || !gamma.is_finite())
+        {
+            return Err(format!("Gamma {} must be greater than 0.", gamma));
+        }
+        for (name, factor) in [("Contrast", self.contrast), ("Saturation", self.saturation)] {
+            if let Some(factor) = factor.filter(|factor| *factor < 0.0 || !factor.is_finite()) {
+                return Err(format!("{} factor {} must not be negative.", name, factor));
+            }
+        }
+        if let Some(unsharp_mask) = self
+            .unsharp_mask
+            .filter(|unsharp_mask| unsharp_mask.sigma <= 0.0 || !unsharp_mask.sigma.is_finite())
+        {
+            return Err(format!(
+                "Unsharp mask sigma {} must be greater than 0.",
+                unsharp_mask.sigma
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Preprocessing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut steps = Vec::new();
+        if self.auto_levels {
+            steps.push("auto-levels".to_string());
+        }
+        if let Some(gamma) = self.gamma {
+            steps.push(format!("gamma {}", gamma));
+        }
+        if let Some(contrast) = self.contrast {
+            steps.push(format!("contrast {}", contrast));
+        }
+        if let Some(saturation) = self.saturation {
+            steps.push(format!("saturation {}", saturation));
+        }
+        if let Some(unsharp_mask) = self.unsharp_mask {
+            steps.push(format!(
+                "unsharp mask {} (threshold {})",
+                unsharp_mask.sigma, unsharp_mask.threshold
+            ));
+        }
+
+        if steps.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", steps.join(", "))
+        }
+    }
+}
+
+fn map_channels(img: &mut RgbImage, mapping: impl Fn(f32) -> f32) {
+    for pixel in img.pixels_mut() {
+        for value in pixel.0.iter_mut() {
+            *value = mapping(*value as f32).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+fn auto_levels(img: &mut RgbImage) {
+    let mut histogram = [0usize; 0x100];
+    for pixel in img.pixels() {
+        for value in pixel.0 {
+            histogram[value as usize] += 1;
+        }
+    }
+    let outliers = img.pixels().len() * 3 / 200;
+    let low = find_level(&histogram, outliers, 0..0x100);
+    let high = find_level(&histogram, outliers, (0..0x100).rev());
+    if high <= low {
+        return;
+    }
+
+    map_channels(img, |value| (value - low) * 255.0 / (high - low));
+}
+
+/// First value (in the order of the passed values) after skipping the passed number of pixels
+fn find_level(
+    histogram: &[usize],
+    outliers: usize,
+    mut values: impl Iterator<Item = usize>,
+) -> f32 {
+    let mut count = 0;
+    values
+        .find(|&value| {
+            count += histogram[value];
+            count > outliers
+        })
+        .unwrap_or(0) as f32
+}
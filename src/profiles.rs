@@ -1,8 +1,52 @@
+use image::Rgb;
+
 use crate::utils::CharsetDefinition;
 
+#[derive(Clone, Default)]
 pub struct Character {
     pub charset: u32,
     pub code: u8,
+    /// Palette index of the foreground color
+    pub foreground: u8,
+    /// Palette index of the background color
+    pub background: u8,
+    /// Whether the cell blinks (only if the machine supports it)
+    pub blink: bool,
+    /// Whether the cell is displayed in multicolor mode
+    pub multicolor: bool,
+    /// Index of the global background color (extended background color mode)
+    pub background_index: u8,
+}
+
+/// Describes how colors of the palette can be assigned to the screen cells
+pub enum ColorMode {
+    /// All cells share the same foreground and background color
+    Fixed { foreground: u8, background: u8 },
+    /// The foreground color can be chosen per cell while all cells share the same background color
+    Foreground,
+    /// Foreground and background color can be chosen per cell
+    ForegroundBackground,
+    /// C64 multicolor mode
+    ///
+    /// Each cell is either displayed like in `Foreground` mode or in multicolor mode where pairs of
+    /// horizontally adjacent pixels select the background color, one of two other global colors
+    /// or the cell color. Cell colors are limited to the first `cell_colors` palette colors.
+    Multicolor { cell_colors: u8 },
+    /// C64 extended background color mode
+    ///
+    /// Like `Foreground` mode, but each cell can select one of the global background colors. Only
+    /// the first `codes` characters of the charset are usable.
+    ExtendedBackground { codes: usize },
+}
+
+impl ColorMode {
+    /// Number of characters of the charset that can be displayed
+    pub fn usable_codes(&self) -> usize {
+        match self {
+            ColorMode::ExtendedBackground { codes } => *codes,
+            _ => 0x100,
+        }
+    }
 }
 
 pub struct MachineProfile {
@@ -10,7 +54,24 @@ pub struct MachineProfile {
     pub lines: u32,
     pub columns: u32,
     pub character_ram_mapping: fn(character: &Character) -> u8,
+    /// Code of the character that displays the passed text character (in the first charset)
+    pub screen_code_mapping: fn(character: char) -> Option<u8>,
     pub color_ram_mapping: Option<fn(character: &Character) -> u8>,
+    /// Colors the machine can display
+    ///
+    /// The index of a color within the palette is the value that selects it in the color RAM.
+    pub palette: &'static [Rgb<u8>],
+    pub color_mode: ColorMode,
+    /// Addresses of the registers that hold the colors shared by all cells (background color first)
+    pub global_color_registers: &'static [u16],
+    /// Address of the register that holds the color of the border around the screen
+    pub border_color_register: Option<u16>,
+    pub supports_blinking: bool,
+    /// Character codes that are never used in the output (e.g. codes that are treated specially
+    /// by the machine's software)
+    pub excluded_codes: &'static [u8],
+    /// Width of a pixel relative to its height as displayed by the machine
+    pub pixel_aspect_ratio: f32,
     pub charset_definition: CharsetDefinition,
     // static PNG data that contains the charset
     pub charsets: &'static [&'static [u8]],
@@ -18,6 +79,8 @@ pub struct MachineProfile {
 
 pub const AVAILABLE_PROFILES: &[&MachineProfile] = &[
     &C64_PROFILE,
+    &C64_MULTICOLOR_PROFILE,
+    &C64_EXTENDED_BACKGROUND_PROFILE,
     &KC87_PROFILE,
     &SHARPMZ_PROFILE,
     &Z1013_PROFILE,
@@ -27,6 +90,9 @@ pub const AVAILABLE_PROFILES: &[&MachineProfile] = &[
 ///
 /// Character RAM: 0x0400
 /// Color RAM: 0xd800
+/// Border color: 0xd020
+/// Background color: 0xd021
+/// Pixel aspect ratio: 0.936 (PAL)
 ///
 /// https://www.c64-wiki.com/wiki/Color_RAM
 /// https://www.c64-wiki.com/wiki/Color
@@ -35,14 +101,109 @@ pub const C64_PROFILE: MachineProfile = MachineProfile {
     lines: 25,
     columns: 40,
     character_ram_mapping: |character| character.code,
-    color_ram_mapping: Some(|_character| 0x01),
+    screen_code_mapping: c64_screen_code,
+    color_ram_mapping: Some(|character| character.foreground),
+    palette: &C64_PALETTE,
+    color_mode: ColorMode::Foreground,
+    global_color_registers: &[0xd021],
+    border_color_register: Some(0xd020),
+    supports_blinking: false,
+    excluded_codes: &[],
+    pixel_aspect_ratio: 0.936,
+    charset_definition: CharsetDefinition {
+        character_width: 8,
+        character_height: 8,
+        inverted: false,
+    },
+    charsets: &[include_bytes!("c64/charset_0.png")],
+};
+
+/// C64 multicolor profile
+///
+/// Character RAM: 0x0400
+/// Color RAM: 0xd800
+/// Border color: 0xd020
+/// Background colors: 0xd021, 0xd022, 0xd023
+///
+/// Multicolor mode has to be enabled by setting bit 4 of 0xd016.
+///
+/// https://www.c64-wiki.com/wiki/Character_set#Multicolor_mode
+pub const C64_MULTICOLOR_PROFILE: MachineProfile = MachineProfile {
+    identifier: "c64mc",
+    lines: 25,
+    columns: 40,
+    character_ram_mapping: |character| character.code,
+    screen_code_mapping: c64_screen_code,
+    color_ram_mapping: Some(|character| (character.multicolor as u8) << 3 | character.foreground),
+    palette: &C64_PALETTE,
+    color_mode: ColorMode::Multicolor { cell_colors: 8 },
+    global_color_registers: &[0xd021, 0xd022, 0xd023],
+    border_color_register: Some(0xd020),
+    supports_blinking: false,
+    excluded_codes: &[],
+    pixel_aspect_ratio: 0.936,
+    charset_definition: CharsetDefinition {
+        character_width: 8,
+        character_height: 8,
+        inverted: false,
+    },
+    charsets: &[include_bytes!("c64/charset_0.png")],
+};
+
+/// C64 extended background color profile
+///
+/// Character RAM: 0x0400
+/// Color RAM: 0xd800
+/// Border color: 0xd020
+/// Background colors: 0xd021, 0xd022, 0xd023, 0xd024
+///
+/// Extended background color mode has to be enabled by setting bit 6 of 0xd011.
+///
+/// https://www.c64-wiki.com/wiki/Extended_color_mode
+pub const C64_EXTENDED_BACKGROUND_PROFILE: MachineProfile = MachineProfile {
+    identifier: "c64ecm",
+    lines: 25,
+    columns: 40,
+    character_ram_mapping: |character| character.background_index << 6 | character.code,
+    screen_code_mapping: c64_screen_code,
+    color_ram_mapping: Some(|character| character.foreground),
+    palette: &C64_PALETTE,
+    color_mode: ColorMode::ExtendedBackground { codes: 0x40 },
+    global_color_registers: &[0xd021, 0xd022, 0xd023, 0xd024],
+    border_color_register: Some(0xd020),
+    supports_blinking: false,
+    excluded_codes: &[],
+    pixel_aspect_ratio: 0.936,
     charset_definition: CharsetDefinition {
         character_width: 8,
         character_height: 8,
+        inverted: false,
     },
     charsets: &[include_bytes!("c64/charset_0.png")],
 };
 
+/// VIC-II colors (as measured by Pepto)
+///
+/// https://www.pepto.de/projects/colorvic/
+const C64_PALETTE: [Rgb<u8>; 16] = [
+    Rgb([0x00, 0x00, 0x00]), // black
+    Rgb([0xff, 0xff, 0xff]), // white
+    Rgb([0x68, 0x37, 0x2b]), // red
+    Rgb([0x70, 0xa4, 0xb2]), // cyan
+    Rgb([0x6f, 0x3d, 0x86]), // purple
+    Rgb([0x58, 0x8d, 0x43]), // green
+    Rgb([0x35, 0x28, 0x79]), // blue
+    Rgb([0xb8, 0xc7, 0x6f]), // yellow
+    Rgb([0x6f, 0x4f, 0x25]), // orange
+    Rgb([0x43, 0x39, 0x00]), // brown
+    Rgb([0x9a, 0x67, 0x59]), // light red
+    Rgb([0x44, 0x44, 0x44]), // dark grey
+    Rgb([0x6c, 0x6c, 0x6c]), // grey
+    Rgb([0x9a, 0xd2, 0x84]), // light green
+    Rgb([0x6c, 0x5e, 0xb5]), // light blue
+    Rgb([0x95, 0x95, 0x95]), // light grey
+];
+
 /// KC 87 profile
 ///
 /// Character RAM: 0xec00
@@ -54,18 +215,42 @@ pub const KC87_PROFILE: MachineProfile = MachineProfile {
     lines: 24,
     columns: 40,
     character_ram_mapping: |character| character.code,
-    color_ram_mapping: Some(|_character| 0b01110000),
+    screen_code_mapping: ascii_screen_code,
+    color_ram_mapping: Some(|character| {
+        (character.blink as u8) << 7 | character.foreground << 4 | character.background
+    }),
+    palette: &KC87_PALETTE,
+    color_mode: ColorMode::ForegroundBackground,
+    global_color_registers: &[],
+    border_color_register: None,
+    supports_blinking: true,
+    excluded_codes: &[],
+    pixel_aspect_ratio: 1.0,
     charset_definition: CharsetDefinition {
         character_width: 8,
         character_height: 8,
+        inverted: false,
     },
     charsets: &[include_bytes!("kc87/charset.png")],
 };
 
+/// KC 87 colors (bit 0: red, bit 1: green, bit 2: blue)
+const KC87_PALETTE: [Rgb<u8>; 8] = [
+    Rgb([0x00, 0x00, 0x00]), // black
+    Rgb([0xff, 0x00, 0x00]), // red
+    Rgb([0x00, 0xff, 0x00]), // green
+    Rgb([0xff, 0xff, 0x00]), // yellow
+    Rgb([0x00, 0x00, 0xff]), // blue
+    Rgb([0xff, 0x00, 0xff]), // magenta
+    Rgb([0x00, 0xff, 0xff]), // cyan
+    Rgb([0xff, 0xff, 0xff]), // white
+];
+
 /// Sharp MZ profile
 ///
 /// Character RAM: 0xd000
 /// Color RAM: 0xd800
+/// Pixel aspect ratio: 0.832 (PAL, dot clock of 8.87 MHz)
 ///
 /// https://original.sharpmz.org/mz-700/colorvram.htm
 /// https://original.sharpmz.org/mz-700/codetable.htm
@@ -74,10 +259,21 @@ pub const SHARPMZ_PROFILE: MachineProfile = MachineProfile {
     lines: 25,
     columns: 40,
     character_ram_mapping: |character| character.code,
-    color_ram_mapping: Some(|character| if character.charset == 0 { 0x07 } else { 0x87 }),
+    screen_code_mapping: sharpmz_screen_code,
+    color_ram_mapping: Some(|character| {
+        (character.charset as u8) << 7 | character.foreground << 4 | character.background
+    }),
+    palette: &SHARPMZ_PALETTE,
+    color_mode: ColorMode::ForegroundBackground,
+    global_color_registers: &[],
+    border_color_register: None,
+    supports_blinking: false,
+    excluded_codes: &[],
+    pixel_aspect_ratio: 0.832,
     charset_definition: CharsetDefinition {
         character_width: 8,
         character_height: 8,
+        inverted: true,
     },
     charsets: &[
         include_bytes!("sharpmz/charset_0.png"),
@@ -85,6 +281,18 @@ pub const SHARPMZ_PROFILE: MachineProfile = MachineProfile {
     ],
 };
 
+/// MZ-700 colors (bit 0: blue, bit 1: red, bit 2: green)
+const SHARPMZ_PALETTE: [Rgb<u8>; 8] = [
+    Rgb([0x00, 0x00, 0x00]), // black
+    Rgb([0x00, 0x00, 0xff]), // blue
+    Rgb([0xff, 0x00, 0x00]), // red
+    Rgb([0xff, 0x00, 0xff]), // magenta
+    Rgb([0x00, 0xff, 0x00]), // green
+    Rgb([0x00, 0xff, 0xff]), // cyan
+    Rgb([0xff, 0xff, 0x00]), // yellow
+    Rgb([0xff, 0xff, 0xff]), // white
+];
+
 /// Z 1013 Profile
 ///
 /// Character RAM: 0xec00
@@ -95,10 +303,82 @@ pub const Z1013_PROFILE: MachineProfile = MachineProfile {
     lines: 32,
     columns: 32,
     character_ram_mapping: |character| character.code,
+    screen_code_mapping: ascii_screen_code,
     color_ram_mapping: None,
+    palette: &Z1013_PALETTE,
+    color_mode: ColorMode::Fixed {
+        foreground: 0x01,
+        background: 0x00,
+    },
+    global_color_registers: &[],
+    border_color_register: None,
+    supports_blinking: false,
+    excluded_codes: &[],
+    pixel_aspect_ratio: 1.0,
     charset_definition: CharsetDefinition {
         character_width: 8,
         character_height: 8,
+        inverted: false,
     },
     charsets: &[include_bytes!("z1013/charset.png")],
 };
+
+/// Z 1013 colors (monochrome display)
+const Z1013_PALETTE: [Rgb<u8>; 2] = [
+    Rgb([0x00, 0x00, 0x00]), // black
+    Rgb([0xff, 0xff, 0xff]), // white
+];
+
+/// Screen codes of the C64 charset (uppercase letters and graphics characters)
+fn c64_screen_code(character: char) -> Option<u8> {
+    match character.to_ascii_uppercase() {
+        '@' => Some(0x00),
+        'A'..='Z' => Some(character.to_ascii_uppercase() as u8 - b'A' + 0x01),
+        '[' => Some(0x1b),
+        '£' => Some(0x1c),
+        ']' => Some(0x1d),
+        ' '..='?' => Some(character as u8),
+        _ => None,
+    }
+}
+
+/// Screen codes of charsets that follow ASCII for printable characters
+fn ascii_screen_code(character: char) -> Option<u8> {
+    match character {
+        ' '..='~' => Some(character as u8),
+        _ => None,
+    }
+}
+
+/// Display codes of the Sharp MZ charset
+fn sharpmz_screen_code(character: char) -> Option<u8> {
+    match character {
+        ' ' => Some(0x00),
+        'A'..='Z' => Some(character as u8 - b'A' + 0x01),
+        '0'..='9' => Some(character as u8 - b'0' + 0x20),
+        '-' => Some(0x2a),
+        '=' => Some(0x2b),
+        ';' => Some(0x2c),
+        '/' => Some(0x2d),
+        '.' => Some(0x2e),
+        ',' => Some(0x2f),
+        '?' => Some(0x49),
+        '<' => Some(0x51),
+        '[' => Some(0x52),
+        ']' => Some(0x54),
+        '@' => Some(0x55),
+        '!' => Some(0x61),
+        '"' => Some(0x62),
+        '#' => Some(0x63),
+        '$' => Some(0x64),
+        '%' => Some(0x65),
+        '&' => Some(0x66),
+        '(' => Some(0x68),
+        ')' => Some(0x69),
+        '+' => Some(0x6a),
+        '*' => Some(0x6b),
+        'a'..='o' => Some(character as u8 - b'a' + 0x81),
+        'p'..='z' => Some(character as u8 - b'p' + 0x90),
+        _ => None,
+    }
+}
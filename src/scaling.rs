@@ -0,0 +1,122 @@
+use std::str::FromStr;
+
+use image::{imageops, imageops::FilterType, DynamicImage, Rgb, RgbImage};
+
+use crate::utils::parse_identifier;
+
+/// How the image is scaled to the dimension of the screen
+#[derive(Clone, Copy, Debug, Default)]
+pub enum FitMode {
+    /// Don't scale the image (the screen shows its top left part)
+    #[default]
+    None,
+    /// Scale the image to the screen without keeping its aspect ratio
+    Stretch,
+    /// Scale the whole image into the screen and fill the remaining area (letterbox)
+    Fit,
+    /// Scale the image to cover the whole screen and cut off what doesn't fit (centered)
+    Fill,
+}
+
+const FIT_MODE_IDENTIFIERS: &[(&str, FitMode)] = &[
+    ("none", FitMode::None),
+    ("stretch", FitMode::Stretch),
+    ("fit", FitMode::Fit),
+    ("fill", FitMode::Fill),
+];
+
+impl FromStr for FitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_identifier(s, FIT_MODE_IDENTIFIERS, "fit mode")
+    }
+}
+
+/// Settings for scaling the image to the dimension of the screen
+#[derive(Clone, Copy, Debug)]
+pub struct Scaling {
+    pub fit_mode: FitMode,
+    /// Filter used for resampling the image
+    pub filter: FilterType,
+    /// Color of the area that is not covered by the image (fit mode `Fit`)
+    pub fill_color: Rgb<u8>,
+}
+
+impl Default for Scaling {
+    fn default() -> Self {
+        Self {
+            fit_mode: FitMode::None,
+            filter: FilterType::Lanczos3,
+            fill_color: Rgb([0x00, 0x00, 0x00]),
+        }
+    }
+}
+
+impl Scaling {
+    /// Scale the image to the passed dimension (in pixels of the screen)
+    ///
+    /// The image is expected to have square pixels. The pixel aspect ratio of the screen (width
+    /// of a pixel relative to its height) is compensated when keeping the image's aspect ratio.
+    pub fn apply(
+        &self,
+        img: &DynamicImage,
+        width: u32,
+        height: u32,
+        pixel_aspect_ratio: f32,
+    ) -> DynamicImage {
+        let pixel_aspect_ratio = pixel_aspect_ratio as f64;
+        // aspect ratio of the image in screen pixels
+        let img_aspect_ratio = img.width() as f64 / img.height() as f64 / pixel_aspect_ratio;
+        let screen_aspect_ratio = width as f64 / height as f64;
+        match self.fit_mode {
+            FitMode::None => img.clone(),
+            FitMode::Stretch => img.resize_exact(width, height, self.filter),
+            FitMode::Fit => {
+                let (scaled_width, scaled_height) = if img_aspect_ratio > screen_aspect_ratio {
+                    (width, (width as f64 / img_aspect_ratio).round() as u32)
+                } else {
+                    ((height as f64 * img_aspect_ratio).round() as u32, height)
+                };
+                let scaled = img
+                    .resize_exact(scaled_width.max(1), scaled_height.max(1), self.filter)
+                    .into_rgb8();
+                let mut screen = RgbImage::from_pixel(width, height, self.fill_color);
+                imageops::overlay(
+                    &mut screen,
+                    &scaled,
+                    ((width - scaled.width()) / 2) as i64,
+                    ((height - scaled.height()) / 2) as i64,
+                );
+
+                DynamicImage::from(screen)
+            }
+            FitMode::Fill => {
+                let (scaled_width, scaled_height) = if img_aspect_ratio > screen_aspect_ratio {
+                    ((height as f64 * img_aspect_ratio).round() as u32, height)
+                } else {
+                    (width, (width as f64 / img_aspect_ratio).round() as u32)
+                };
+                let scaled = img.resize_exact(
+                    scaled_width.max(width),
+                    scaled_height.max(height),
+                    self.filter,
+                );
+
+                scaled.crop_imm(
+                    (scaled.width() - width) / 2,
+                    (scaled.height() - height) / 2,
+                    width,
+                    height,
+                )
+            }
+        }
+    }
+}
+
+/// Scale an image with the passed pixel aspect ratio to square pixels (by changing its width)
+pub fn to_square_pixels(img: &RgbImage, pixel_aspect_ratio: f32) -> RgbImage {
+    let width = (img.width() as f32 * pixel_aspect_ratio).round().max(1.0) as u32;
+
+    imageops::resize(img, width, img.height(), FilterType::Triangle)
+}
@@ -0,0 +1,70 @@
+use image::{DynamicImage, Rgb, RgbImage};
+
+use crate::{color::ColorDistance, utils::image_diff};
+
+/// Mean color distance of the pixels of each cell between input image and preview (row by row)
+pub fn get_cell_errors(
+    input_img: &DynamicImage,
+    preview: &RgbImage,
+    cell_width: u32,
+    cell_height: u32,
+    color_distance: ColorDistance,
+) -> Vec<u32> {
+    let mut cell_errors = Vec::new();
+    for y in (0..preview.height()).step_by(cell_height as usize) {
+        for x in (0..preview.width()).step_by(cell_width as usize) {
+            let tile = input_img
+                .crop_imm(x, y, cell_width, cell_height)
+                .into_rgb8();
+            let character = image::imageops::crop_imm(preview, x, y, cell_width, cell_height);
+            cell_errors.push(image_diff(&tile, &character.to_image(), |a, b| {
+                color_distance.diff(a, b)
+            }));
+        }
+    }
+
+    cell_errors
+}
+
+/// Peak signal-to-noise ratio (in dB) of the preview compared to the input image
+///
+/// Returns `None` if the preview is identical to the input image (the PSNR would be infinite).
+pub fn get_psnr(input_img: &DynamicImage, preview: &RgbImage) -> Option<f64> {
+    let input_img = input_img
+        .crop_imm(0, 0, preview.width(), preview.height())
+        .into_rgb8();
+    let mut squared_error_sum = 0.0;
+    for (a, b) in input_img.pixels().zip(preview.pixels()) {
+        for channel in 0..3 {
+            squared_error_sum += (a[channel] as f64 - b[channel] as f64).powi(2);
+        }
+    }
+    if squared_error_sum == 0.0 {
+        return None;
+    }
+    let mean_squared_error = squared_error_sum / (preview.width() * preview.height() * 3) as f64;
+
+    Some(10.0 * (255.0f64.powi(2) / mean_squared_error).log10())
+}
+
+/// Image of the screen's dimension where each cell is colored by its error
+///
+/// The colors range from black (no error) over red and yellow to white (an error of 191 or more),
+/// so heatmaps of different conversions can be compared.
+pub fn create_error_heatmap(
+    cell_errors: &[u32],
+    columns: u32,
+    cell_width: u32,
+    cell_height: u32,
+) -> RgbImage {
+    let lines = cell_errors.len() as u32 / columns;
+    RgbImage::from_fn(columns * cell_width, lines * cell_height, |x, y| {
+        let error = cell_errors[((y / cell_height) * columns + x / cell_width) as usize];
+        let level = error.min(191) * 4;
+        Rgb([
+            level.min(255) as u8,
+            level.saturating_sub(256).min(255) as u8,
+            level.saturating_sub(512) as u8,
+        ])
+    })
+}
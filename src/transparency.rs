@@ -0,0 +1,36 @@
+use image::{DynamicImage, Rgb, RgbImage};
+
+/// Handling of the alpha channel of the image
+#[derive(Clone, Copy, Debug)]
+pub struct Transparency {
+    /// Color the image is composited onto
+    pub background: Rgb<u8>,
+    /// Code of the character that fills cells whose pixels are all fully transparent
+    ///
+    /// These cells are not matched. Their colors default to the ones of text overlays.
+    pub fill_code: Option<u8>,
+}
+
+impl Transparency {
+    /// Blend the pixels of the image with the background color according to their alpha value
+    pub fn composite(&self, img: &DynamicImage) -> DynamicImage {
+        let img = img.to_rgba8();
+        DynamicImage::from(RgbImage::from_fn(img.width(), img.height(), |x, y| {
+            let pixel = img.get_pixel(x, y);
+            let alpha = pixel[3] as u32;
+            Rgb([0, 1, 2].map(|c| {
+                ((pixel[c] as u32 * alpha + self.background[c] as u32 * (255 - alpha) + 127) / 255)
+                    as u8
+            }))
+        }))
+    }
+}
+
+/// Grayscale image (stored as RGB to allow the same scaling as the image) of the alpha values
+pub fn get_alpha_mask(img: &DynamicImage) -> DynamicImage {
+    let img = img.to_rgba8();
+    DynamicImage::from(RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let alpha = img.get_pixel(x, y)[3];
+        Rgb([alpha, alpha, alpha])
+    }))
+}
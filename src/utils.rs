@@ -1,32 +1,90 @@
 use std::io::Cursor;
 
-use image::{ImageReader, RgbImage};
+use image::{ImageReader, Rgb, RgbImage};
+
+use crate::glyph::Glyph;
 
 pub struct CharsetDefinition {
     pub character_width: u32,
     pub character_height: u32,
+    /// Set pixels are drawn dark on bright background in the charset image
+    pub inverted: bool,
 }
 
-pub fn image_diff(a: &RgbImage, b: &RgbImage) -> u32 {
+pub fn image_diff(
+    a: &RgbImage,
+    b: &RgbImage,
+    pixel_diff: impl Fn(&Rgb<u8>, &Rgb<u8>) -> u32,
+) -> u32 {
     assert!(a.width() == b.width() && a.height() == b.height());
     let width = a.width();
     let height = a.height();
     let mut diff: u32 = 0;
     for x in 0..width {
         for y in 0..height {
-            let pa = a.get_pixel(x, y);
-            let pb = b.get_pixel(x, y);
-            diff = diff
-                + pa[0].abs_diff(pb[0]) as u32
-                + pa[1].abs_diff(pb[1]) as u32
-                + pa[2].abs_diff(pb[2]) as u32;
+            diff += pixel_diff(a.get_pixel(x, y), b.get_pixel(x, y));
         }
     }
 
     diff / (width * height)
 }
 
-pub fn load_charset(charset_data: &[u8], def: &CharsetDefinition) -> Vec<RgbImage> {
+/// Look up the value of an identifier in a table (for implementing `FromStr`)
+///
+/// The error message names the kind of value and lists the available identifiers.
+pub fn parse_identifier<T: Copy>(
+    s: &str,
+    identifiers: &[(&str, T)],
+    name: &str,
+) -> Result<T, String> {
+    if let Some((_, value)) = identifiers.iter().find(|(identifier, _)| *identifier == s) {
+        return Ok(*value);
+    }
+
+    let available = identifiers
+        .iter()
+        .map(|(identifier, _)| *identifier)
+        .collect::<Vec<&str>>()
+        .join(", ");
+
+    Err(format!(
+        "Unknown {} \"{}\".\nAvailable {}s: {}",
+        name, s, name, available
+    ))
+}
+
+/// Average color of all pixels of an image
+pub fn mean_color(img: &RgbImage) -> [f32; 3] {
+    let mut sums = [0.0; 3];
+    for pixel in img.pixels() {
+        for (sum, value) in sums.iter_mut().zip(pixel.0) {
+            *sum += value as f32;
+        }
+    }
+    let pixel_count = (img.width() * img.height()) as f32;
+
+    sums.map(|sum| sum / pixel_count)
+}
+
+/// Add an offset to each channel of all pixels of an image (clamped to the valid range)
+pub fn shift_colors(img: &RgbImage, offset: [f32; 3]) -> RgbImage {
+    let mut shifted = img.clone();
+    for pixel in shifted.pixels_mut() {
+        for (value, offset) in pixel.0.iter_mut().zip(offset) {
+            *value = (*value as f32 + offset).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    shifted
+}
+
+/// Whether a pixel of a charset image belongs to the glyph (foreground) or not (background)
+pub fn is_pixel_set(pixel: &Rgb<u8>) -> bool {
+    (pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3 > 0x80
+}
+
+/// Load the glyphs of a charset image
+pub fn load_charset(charset_data: &[u8], def: &CharsetDefinition) -> Vec<Glyph> {
     let cursor = Cursor::new(charset_data);
     let reader = ImageReader::with_format(cursor, image::ImageFormat::Png);
     let charset = reader.decode().expect("Unable to decode charset image");
@@ -37,11 +95,15 @@ pub fn load_charset(charset_data: &[u8], def: &CharsetDefinition) -> Vec<RgbImag
         let x = 1 + column as u32 * (def.character_width + 1);
         let y = 1 + row as u32 * (def.character_height + 1);
 
-        characters.push(
-            charset
+        let mut glyph = Glyph::from_image(
+            &charset
                 .crop_imm(x, y, def.character_width, def.character_height)
                 .into_rgb8(),
         );
+        if def.inverted {
+            glyph.invert();
+        }
+        characters.push(glyph);
     }
     characters
 }